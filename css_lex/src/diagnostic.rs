@@ -0,0 +1,91 @@
+use crate::lexer::Span;
+use std::fmt;
+
+// From the parse errors in http://dev.w3.org/csswg/css-syntax/#tokenization
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum DiagnosticCode {
+    // The input ends inside a string
+    UnterminatedString,
+    // A string contains an unescaped newline, giving a bad string
+    NewlineInString,
+    // The input ends inside a comment
+    UnterminatedComment,
+    // The input ends inside a url
+    UnterminatedUrl,
+    // An unquoted url contains a quote, `(` or a non-printable character,
+    // giving a bad url
+    BadUrlCharacter,
+    // A `\` is followed by a newline outside of a string
+    InvalidEscape,
+    // A `\` is the last character of the input outside of a string
+    EscapedEof,
+}
+
+impl DiagnosticCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticCode::UnterminatedString => "unterminated-string",
+            DiagnosticCode::NewlineInString => "newline-in-string",
+            DiagnosticCode::UnterminatedComment => "unterminated-comment",
+            DiagnosticCode::UnterminatedUrl => "unterminated-url",
+            DiagnosticCode::BadUrlCharacter => "bad-url-character",
+            DiagnosticCode::InvalidEscape => "invalid-escape",
+            DiagnosticCode::EscapedEof => "escaped-eof",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            DiagnosticCode::UnterminatedString => {
+                "string is not closed before the end of the input"
+            }
+            DiagnosticCode::NewlineInString => "newline in string; escape it with `\\`",
+            DiagnosticCode::UnterminatedComment => {
+                "comment is not closed before the end of the input"
+            }
+            DiagnosticCode::UnterminatedUrl => "url is not closed before the end of the input",
+            DiagnosticCode::BadUrlCharacter => {
+                "quotes, `(` and non-printable characters must be escaped in an unquoted url"
+            }
+            DiagnosticCode::InvalidEscape => "`\\` followed by a newline is not an escape",
+            DiagnosticCode::EscapedEof => "`\\` at the end of the input",
+        }
+    }
+}
+
+/**
+ * A parse error found by the tokenizer, which still produces a token for the
+ * input and carries on.
+ *
+ * Unterminated strings, comments and urls span from their start to the end of
+ * the input, other errors the offending characters.
+ */
+#[derive(PartialEq, Eq, Clone, Debug, Hash)]
+pub struct Diagnostic {
+    pub code: DiagnosticCode,
+    pub message: &'static str,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(code: DiagnosticCode, span: Span) -> Diagnostic {
+        Diagnostic {
+            code,
+            message: code.message(),
+            span,
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}: {}", self.code, self.span, self.message)
+    }
+}
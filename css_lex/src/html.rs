@@ -0,0 +1,317 @@
+use crate::lexer::*;
+use crate::line_index::*;
+use crate::minify::*;
+use std::cmp;
+use std::ops::Range;
+use std::sync::Arc;
+
+// The longest character reference `decode_reference` looks for a `;` in,
+// enough for any code point with a few leading zeros
+static MAX_REFERENCE_LEN: usize = 16;
+
+// Elements whose content is text which may contain `<style` without starting
+// an element.
+static RAW_TEXT_ELEMENTS: [&str; 3] = ["script", "textarea", "title"];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StyleSource {
+    // The content of a `<style>` element, a whole stylesheet
+    Element,
+    // The value of a `style` attribute, a list of declarations
+    Attribute,
+}
+
+/**
+ * A piece of CSS found in an HTML document.
+ */
+#[derive(Debug)]
+pub struct EmbeddedStyle {
+    pub source: StyleSource,
+    // Where the CSS is in the document, not including any quotes
+    pub range: Range<usize>,
+    // The CSS with character references decoded and preprocessed
+    pub css: String,
+    // Tokens of `css`, with locations in the document
+    pub tokens: Vec<Node>,
+}
+
+impl EmbeddedStyle {
+    /**
+     * Minify the CSS, treating an attribute value as the declarations of a
+     * block.
+     */
+    pub fn minify(&self) -> String {
+        match self.source {
+            StyleSource::Element => minify_tokens(&self.tokens),
+            StyleSource::Attribute => minify_declaration_list(&self.tokens),
+        }
+    }
+}
+
+/**
+ * Decoded text along with where its bytes came from in the document.
+ */
+struct MappedText {
+    text: String,
+    map: OffsetMap,
+}
+
+impl MappedText {
+    fn new(start: usize) -> MappedText {
+        MappedText {
+            text: String::new(),
+            map: OffsetMap::new(start),
+        }
+    }
+
+    /**
+     * Append `replacement`, which stands for the document bytes starting at
+     * `source` and ending at `source_end`.
+     */
+    fn push(&mut self, replacement: &str, source: usize, source_end: usize) {
+        self.map.anchor(self.text.len(), source);
+        self.text.push_str(replacement);
+        self.map.anchor(self.text.len(), source_end);
+    }
+}
+
+/**
+ * Preprocess `html[range]`, decoding character references if it is an
+ * attribute value.
+ *
+ * Characters from references are preprocessed along with the rest, so `&#13;`
+ * becomes a newline, and forms a single one with a `\n` after it.
+ */
+fn extract(html: &str, range: Range<usize>, source: StyleSource) -> MappedText {
+    let mut mapped = MappedText::new(range.start);
+    let bytes = html.as_bytes();
+    let mut run_start = range.start;
+    let mut position = range.start;
+    // Whether the last character was a `\r`, so that a `\n` is part of the
+    // same newline
+    let mut after_cr = false;
+    while position < range.end {
+        let decoded = match bytes[position] {
+            b'&' if source == StyleSource::Attribute => {
+                decode_reference(&html[position..range.end])
+            }
+            b'\n' if !after_cr => None,
+            b @ (b'\r' | b'\n' | b'\x0C' | b'\x00') => Some((b as char, 1)),
+            _ => None,
+        };
+        let (c, len) = match decoded {
+            Some(decoded) => decoded,
+            None => {
+                after_cr = false;
+                position += 1;
+                continue;
+            }
+        };
+        let replacement = match c {
+            '\n' if after_cr => String::new(),
+            '\r' | '\x0C' => "\n".to_string(),
+            '\0' => "\u{FFFD}".to_string(),
+            c => c.to_string(),
+        };
+        after_cr = c == '\r';
+        mapped.push(&html[run_start..position], run_start, position);
+        mapped.push(&replacement, position, position + len);
+        position += len;
+        run_start = position;
+    }
+    mapped.push(&html[run_start..range.end], run_start, range.end);
+    mapped
+}
+
+/**
+ * Decode the character reference `text` starts with, returning the character
+ * and the length of the reference. Only numeric references and the named ones
+ * likely to appear in CSS are decoded.
+ */
+fn decode_reference(text: &str) -> Option<(char, usize)> {
+    let end = text
+        .bytes()
+        .take(MAX_REFERENCE_LEN)
+        .position(|b| b == b';')?;
+    let name = &text[1..end];
+    let c = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{A0}',
+        _ => {
+            let number = name.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)
+                .filter(|&c| c != '\0')
+                .unwrap_or('\u{FFFD}')
+        }
+    };
+    Some((c, end + 1))
+}
+
+fn tokenize_embedded(
+    html: &str,
+    html_lines: &LineIndex,
+    range: Range<usize>,
+    source: StyleSource,
+) -> EmbeddedStyle {
+    let mapped = extract(html, range.clone(), source);
+    let tokens = Tokenizer::new(Arc::new(mapped.text.clone()))
+        .map(|(token, location)| {
+            let start = mapped.map.original_offset(location.span.start);
+            let (line, column) = html_lines.line_column(start, ColumnUnit::Utf8);
+            let location = SourceLocation {
+                line,
+                column,
+                span: Span {
+                    start,
+                    end: mapped.map.original_offset(location.span.end),
+                },
+            };
+            (token, location)
+        })
+        .collect();
+    EmbeddedStyle {
+        source,
+        range,
+        css: mapped.text,
+        tokens,
+    }
+}
+
+/**
+ * Find `needle` in `html` from `from` on, ignoring ASCII case.
+ */
+fn find_ignore_case(html: &str, from: usize, needle: &str) -> Option<usize> {
+    let bytes = html.as_bytes();
+    (from..=bytes.len().checked_sub(needle.len())?)
+        .find(|&start| bytes[start..start + needle.len()].eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/**
+ * Tokenize the CSS in `<style>` elements and `style` attributes of `html`.
+ *
+ * This is a light scan of the document rather than a full HTML parser:
+ * comments and the content of `<script>`, `<textarea>` and `<title>` are
+ * skipped, and tags are read with their attributes, quoted or not. Token
+ * locations are lines, byte columns and spans in `html`.
+ */
+pub fn tokenize_html(html: &str) -> Vec<EmbeddedStyle> {
+    let bytes = html.as_bytes();
+    let html_lines = LineIndex::new(html);
+    let mut styles = Vec::new();
+    let mut position = 0;
+    while position < bytes.len() {
+        if bytes[position] != b'<' {
+            position += 1;
+            continue;
+        }
+        if html[position..].starts_with("<!--") {
+            position = html[position + 4..]
+                .find("-->")
+                .map_or(bytes.len(), |end| position + 4 + end + 3);
+            continue;
+        }
+        if !bytes.get(position + 1).is_some_and(u8::is_ascii_alphabetic) {
+            // A closing tag, doctype or processing instruction
+            position = html[position..]
+                .find('>')
+                .map_or(bytes.len(), |end| position + end + 1);
+            continue;
+        }
+
+        let name_start = position + 1;
+        position = name_start;
+        while position < bytes.len()
+            && !matches!(bytes[position], b'>' | b'/')
+            && !bytes[position].is_ascii_whitespace()
+        {
+            position += 1;
+        }
+        let name = &html[name_start..position];
+
+        // Attributes, up to the end of the tag
+        loop {
+            while position < bytes.len()
+                && (bytes[position].is_ascii_whitespace() || bytes[position] == b'/')
+            {
+                position += 1;
+            }
+            if position >= bytes.len() || bytes[position] == b'>' {
+                position += 1;
+                break;
+            }
+            let attribute_start = position;
+            while position < bytes.len()
+                && !matches!(bytes[position], b'=' | b'>' | b'/')
+                && !bytes[position].is_ascii_whitespace()
+            {
+                position += 1;
+            }
+            let attribute = &html[attribute_start..position];
+            while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if position >= bytes.len() || bytes[position] != b'=' {
+                continue;
+            }
+            position += 1;
+            while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            let value = match bytes.get(position) {
+                Some(&quote) if quote == b'"' || quote == b'\'' => {
+                    let start = position + 1;
+                    let end = html[start..]
+                        .find(quote as char)
+                        .map_or(bytes.len(), |end| start + end);
+                    position = cmp::min(end + 1, bytes.len());
+                    start..end
+                }
+                _ => {
+                    let start = position;
+                    while position < bytes.len()
+                        && bytes[position] != b'>'
+                        && !bytes[position].is_ascii_whitespace()
+                    {
+                        position += 1;
+                    }
+                    start..position
+                }
+            };
+            if attribute.eq_ignore_ascii_case("style") {
+                styles.push(tokenize_embedded(
+                    html,
+                    &html_lines,
+                    value,
+                    StyleSource::Attribute,
+                ));
+            }
+        }
+
+        if name.eq_ignore_ascii_case("style") {
+            let start = cmp::min(position, bytes.len());
+            let end = find_ignore_case(html, start, "</style").unwrap_or(bytes.len());
+            styles.push(tokenize_embedded(
+                html,
+                &html_lines,
+                start..end,
+                StyleSource::Element,
+            ));
+            position = end;
+        } else if let Some(raw) = RAW_TEXT_ELEMENTS
+            .iter()
+            .find(|raw| name.eq_ignore_ascii_case(raw))
+        {
+            let start = cmp::min(position, bytes.len());
+            position = find_ignore_case(html, start, &format!("</{}", raw)).unwrap_or(bytes.len());
+        }
+    }
+    styles
+}
@@ -0,0 +1,193 @@
+use crate::lexer::*;
+use std::ops::Range;
+use std::sync::Arc;
+
+// No token looks more than three characters past its own end, so a token which
+// ends at least this many bytes before an edit cannot have been affected by it.
+static RESTART_LOOKBACK: usize = 3 * 4;
+
+/**
+ * Replace the bytes in `range` with `replacement`.
+ *
+ * Offsets are into the preprocessed input, and `range` must lie on character
+ * boundaries.
+ */
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+/**
+ * The byte offset of every line start in `input`.
+ */
+pub fn line_starts(input: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    starts.extend(
+        input
+            .bytes()
+            .enumerate()
+            .filter(|&(_, b)| b == b'\n')
+            .map(|(i, _)| i + 1),
+    );
+    starts
+}
+
+/**
+ * An edit being applied to a token list.
+ *
+ * Created by `begin_edit`, which finds where re-lexing must start. Tokens are
+ * then lexed from `tokenizer()` up to at least `edit_end`, and `finish` keeps
+ * lexing until the new tokens line up with the old ones again.
+ */
+pub struct EditSplice {
+    pub input: Arc<String>,
+    // Number of old tokens before the restart point, which are kept as is
+    pub keep: usize,
+    pub position: usize,
+    pub line: usize,
+    pub last_line_start: usize,
+    // End of the replacement in the new input
+    pub edit_end: usize,
+    delta: isize,
+}
+
+/**
+ * Apply `edit` to `old_input` and find the restart point in `old_tokens`, which
+ * must be the result of tokenizing `old_input`.
+ *
+ * Re-lexing starts at the last token starting at least `RESTART_LOOKBACK`
+ * bytes before the edit. Every token before it ended before the edited bytes
+ * and never looked at them.
+ */
+pub fn begin_edit(old_input: &str, old_tokens: &[Node], edit: &TextEdit) -> EditSplice {
+    let mut range = edit.range.clone();
+    // A replacement ending in `\r` forms a single newline with a `\n` just
+    // after the edit, so preprocess that `\n` along with it.
+    let replacement =
+        if edit.replacement.ends_with('\r') && old_input[range.end..].starts_with('\n') {
+            range.end += 1;
+            preprocess(&format!("{}\n", edit.replacement))
+        } else {
+            preprocess(&edit.replacement)
+        };
+    let mut input = String::with_capacity(old_input.len() + replacement.len());
+    input.push_str(&old_input[..range.start]);
+    input.push_str(&replacement);
+    input.push_str(&old_input[range.end..]);
+
+    let threshold = range.start.saturating_sub(RESTART_LOOKBACK);
+    let count = old_tokens.partition_point(|(_, loc)| loc.span.start <= threshold);
+
+    let (keep, position, line, last_line_start) = match count.checked_sub(1) {
+        Some(restart) => {
+            let location = &old_tokens[restart].1;
+            (
+                restart,
+                location.span.start,
+                location.line,
+                location.span.start + 1 - location.column,
+            )
+        }
+        None => (0, 0, 1, 0),
+    };
+
+    EditSplice {
+        input: Arc::new(input),
+        keep,
+        position,
+        line,
+        last_line_start,
+        edit_end: range.start + replacement.len(),
+        delta: replacement.len() as isize - range.len() as isize,
+    }
+}
+
+impl EditSplice {
+    /**
+     * A tokenizer over the new input, positioned at the restart point.
+     */
+    pub fn tokenizer(&self) -> Tokenizer {
+        let mut tokenizer = Tokenizer::new(Arc::clone(&self.input));
+        tokenizer.reset_to(TokenizerState {
+            position: self.position,
+            line: self.line,
+            last_line_start: self.last_line_start,
+            diagnostics: 0,
+        });
+        tokenizer
+    }
+
+    /**
+     * Finish the edit, returning the new input and its tokens.
+     *
+     * `relexed` holds the tokens already lexed from the restart point, and
+     * `tokenizer` is positioned just after them. Lexing continues until a token
+     * starts after the edit at the same place, relative to the unchanged text,
+     * as an identical old token. Since tokens never look backwards, the rest of
+     * the old tokens are then reused with their locations shifted.
+     */
+    pub fn finish(
+        self,
+        old_tokens: &[Node],
+        mut relexed: Vec<Node>,
+        mut tokenizer: Tokenizer,
+    ) -> (String, Vec<Node>) {
+        let mut resync = None;
+        for (token, location) in tokenizer.by_ref() {
+            let start = location.span.start;
+            if start >= self.edit_end {
+                let old_start = (start as isize - self.delta) as usize;
+                let old_tokens = &old_tokens[self.keep..];
+                let idx = old_tokens.partition_point(|(_, loc)| loc.span.start < old_start);
+                if let Some((old_token, old_location)) = old_tokens.get(idx) {
+                    if *old_token == token && old_location.span.start == old_start {
+                        resync = Some((self.keep + idx, old_location.clone(), location.clone()));
+                    }
+                }
+            }
+            relexed.push((token, location));
+            if resync.is_some() {
+                break;
+            }
+        }
+        drop(tokenizer);
+
+        let mut nodes = Vec::with_capacity(self.keep + relexed.len());
+        nodes.extend_from_slice(&old_tokens[..self.keep]);
+        nodes.append(&mut relexed);
+        if let Some((idx, old, new)) = resync {
+            for (token, location) in &old_tokens[idx + 1..] {
+                let column = if location.line == old.line {
+                    location.column + new.column - old.column
+                } else {
+                    location.column
+                };
+                let location = SourceLocation {
+                    line: location.line + new.line - old.line,
+                    column,
+                    span: Span {
+                        start: (location.span.start as isize + self.delta) as usize,
+                        end: (location.span.end as isize + self.delta) as usize,
+                    },
+                };
+                nodes.push((token.clone(), location));
+            }
+        }
+
+        let input = Arc::try_unwrap(self.input).unwrap_or_else(|input| (*input).clone());
+        (input, nodes)
+    }
+}
+
+/**
+ * Re-tokenize after an edit, re-lexing only around the edited text.
+ *
+ * `old_tokens` must be the result of tokenizing `old_input`, which is assumed
+ * to have already been preprocessed. Returns the new input and its tokens,
+ * which are the same as tokenizing the new input from scratch.
+ */
+pub fn retokenize(old_input: &str, old_tokens: &[Node], edit: &TextEdit) -> (String, Vec<Node>) {
+    let splice = begin_edit(old_input, old_tokens, edit);
+    let tokenizer = splice.tokenizer();
+    splice.finish(old_tokens, Vec::new(), tokenizer)
+}
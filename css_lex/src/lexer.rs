@@ -1,36 +1,86 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
 use std::str::FromStr;
 use std::{char, str, sync::Arc};
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-#[derive(Clone)]
-pub struct NumericValue {
-    pub representation: String,
+use crate::diagnostic::*;
+use crate::line_index::ColumnUnit;
+
+/**
+ * A number as written. Equality and hashing are exact, so `1.0` and `1` are
+ * different values; `approx_eq` compares the numbers themselves.
+ */
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NumericValue<T = String> {
+    pub representation: T,
     pub value: f64,
     pub int_value: Option<i64>,
 }
 
-#[derive(Eq, PartialEq, Clone)]
+#[derive(Eq, PartialEq, Clone, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SourceLocation {
     pub line: usize,   // First line is 1
     pub column: usize, // First character of a line is at column 1
+    pub span: Span,
+}
+
+/**
+ * The byte offsets of a token in the input, from `start` up to but not
+ * including `end`.
+ *
+ * Offsets are into the text given to `tokenize`, or into the input of a
+ * tokenizer created with `Tokenizer::new`.
+ */
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
-#[derive(PartialEq, Eq, Clone)]
-pub enum Token {
-    Ident(String),
-    Function(String),
-    AtKeyword(String),
-    Hash(String),
-    IDHash(String),
-    String(String),
+/**
+ * A token. String values are `String`s by default; tokens from
+ * `tokenize_borrowed` use `Cow<str>` instead, borrowing values without escapes
+ * from the input.
+ *
+ * With the `serde` feature a token serializes as `{"type": ..., "value": ...}`,
+ * where the type is the variant's name in kebab case and the value is left out
+ * for variants without one. See the README for the full schema.
+ */
+#[derive(PartialEq, Eq, Clone, Debug, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "type", content = "value", rename_all = "kebab-case")
+)]
+pub enum Token<T = String> {
+    Ident(T),
+    Function(T),
+    AtKeyword(T),
+    Hash(T),
+    #[cfg_attr(feature = "serde", serde(rename = "id-hash"))]
+    IDHash(T),
+    String(T),
     BadString,
-    URL(String),
+    #[cfg_attr(feature = "serde", serde(rename = "url"))]
+    URL(T),
+    #[cfg_attr(feature = "serde", serde(rename = "bad-url"))]
     BadURL,
     Delim(char),
-    Number(NumericValue),
-    Percentage(NumericValue),
-    Dimension(NumericValue, String),
+    Number(NumericValue<T>),
+    Percentage(NumericValue<T>),
+    Dimension(NumericValue<T>, T),
+    // This and the match and column tokens are only produced by the legacy
+    // tokenizer
     UnicodeRange(u32, u32),
     IncludeMatch,
     DashMatch,
@@ -38,8 +88,15 @@ pub enum Token {
     SuffixMatch,
     SubstringMatch,
     Column,
-    WhiteSpace,
+    // The whitespace as written, if the tokenizer keeps it
+    #[cfg_attr(feature = "serde", serde(rename = "whitespace"))]
+    WhiteSpace(Option<T>),
+    // A comment including `/*` and `*/`, only produced if the tokenizer keeps
+    // comments
+    Comment(T),
+    #[cfg_attr(feature = "serde", serde(rename = "cdo"))]
     CDO,
+    #[cfg_attr(feature = "serde", serde(rename = "cdc"))]
     CDC,
     Colon,
     Semicolon,
@@ -54,26 +111,259 @@ pub enum Token {
 
 pub type Node = (Token, SourceLocation);
 
-pub struct Tokenizer {
+pub type BorrowedNode<'a> = (Token<Cow<'a, str>>, SourceLocation);
+
+impl<T> Token<T> {
+    fn map_text<U>(self, mut f: impl FnMut(T) -> U) -> Token<U> {
+        let numeric = |value: NumericValue<T>, f: &mut dyn FnMut(T) -> U| NumericValue {
+            representation: f(value.representation),
+            value: value.value,
+            int_value: value.int_value,
+        };
+        match self {
+            Token::Ident(value) => Token::Ident(f(value)),
+            Token::Function(value) => Token::Function(f(value)),
+            Token::AtKeyword(value) => Token::AtKeyword(f(value)),
+            Token::Hash(value) => Token::Hash(f(value)),
+            Token::IDHash(value) => Token::IDHash(f(value)),
+            Token::String(value) => Token::String(f(value)),
+            Token::BadString => Token::BadString,
+            Token::URL(value) => Token::URL(f(value)),
+            Token::BadURL => Token::BadURL,
+            Token::Delim(c) => Token::Delim(c),
+            Token::Number(value) => Token::Number(numeric(value, &mut f)),
+            Token::Percentage(value) => Token::Percentage(numeric(value, &mut f)),
+            Token::Dimension(value, unit) => {
+                let value = numeric(value, &mut f);
+                Token::Dimension(value, f(unit))
+            }
+            Token::UnicodeRange(start, end) => Token::UnicodeRange(start, end),
+            Token::IncludeMatch => Token::IncludeMatch,
+            Token::DashMatch => Token::DashMatch,
+            Token::PrefixMatch => Token::PrefixMatch,
+            Token::SuffixMatch => Token::SuffixMatch,
+            Token::SubstringMatch => Token::SubstringMatch,
+            Token::Column => Token::Column,
+            Token::WhiteSpace(text) => Token::WhiteSpace(text.map(&mut f)),
+            Token::Comment(text) => Token::Comment(f(text)),
+            Token::CDO => Token::CDO,
+            Token::CDC => Token::CDC,
+            Token::Colon => Token::Colon,
+            Token::Semicolon => Token::Semicolon,
+            Token::Comma => Token::Comma,
+            Token::LeftBracket => Token::LeftBracket,
+            Token::RightBracket => Token::RightBracket,
+            Token::LeftParen => Token::LeftParen,
+            Token::RightParen => Token::RightParen,
+            Token::LeftCurlyBracket => Token::LeftCurlyBracket,
+            Token::RightCurlyBracket => Token::RightCurlyBracket,
+        }
+    }
+}
+
+impl Token<Cow<'_, str>> {
+    /**
+     * Copy any borrowed values, giving a token which no longer refers to the
+     * input.
+     */
+    pub fn into_owned(self) -> Token {
+        self.map_text(Cow::into_owned)
+    }
+}
+
+// A string value as read by the tokenizer: a range of the input when it can be
+// taken as is, or a copy when escapes had to be replaced.
+enum Text {
+    Input(usize, usize),
+    Owned(String),
+}
+
+impl Text {
+    #[inline]
+    fn as_str<'a>(&'a self, input: &'a str) -> &'a str {
+        match self {
+            Text::Input(start, end) => &input[*start..*end],
+            Text::Owned(value) => value,
+        }
+    }
+}
+
+type RawToken = Token<Text>;
+
+// Builds a `Text`, keeping to a range of the input until something else has to
+// be added.
+struct TextBuilder {
+    start: usize,
+    end: usize,
+    owned: Option<String>,
+}
+
+impl TextBuilder {
+    #[inline]
+    fn new(start: usize) -> TextBuilder {
+        TextBuilder {
+            start,
+            end: start,
+            owned: None,
+        }
+    }
+
+    // Add `c`, which was just consumed as is from the input ending at `end`.
+    #[inline]
+    fn push_input(&mut self, c: char, end: usize) {
+        match &mut self.owned {
+            Some(value) => value.push(c),
+            None => self.end = end,
+        }
+    }
+
+    // The value so far as a `String`, for adding something not in the input.
+    #[inline]
+    fn owned(&mut self, input: &str) -> &mut String {
+        self.owned
+            .get_or_insert_with(|| input[self.start..self.end].to_string())
+    }
+
+    #[inline]
+    fn finish(self) -> Text {
+        match self.owned {
+            Some(value) => Text::Owned(value),
+            None => Text::Input(self.start, self.end),
+        }
+    }
+}
+
+/**
+ * Text a `Tokenizer` can read from: a `String`, or anything else that derefs
+ * to a `str`, such as a memory-mapped file.
+ */
+pub trait Source: Deref<Target = str> {}
+
+impl<T: Deref<Target = str>> Source for T {}
+
+/**
+ * Tokens of a preprocessed `&str`, borrowing string values from it where they
+ * contain no escapes. Created by `tokenize_borrowed`.
+ */
+#[derive(Debug)]
+pub struct BorrowedTokenizer<'a> {
+    pub tokenizer: Tokenizer<&'a str>,
+}
+
+/**
+ * Settings for a `Tokenizer`. The default tokenizes as the specification
+ * describes.
+ */
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub struct TokenizerOptions {
+    // Emit comments as `Token::Comment` and give whitespace tokens their text,
+    // so that the spans of the tokens cover the whole input
+    pub lossless: bool,
+    // Tokenize as the 2013 draft of the specification did: with unicode
+    // range, match and column tokens, no identifiers starting with `--`, and
+    // a url token for `url(` followed by a quoted string
+    pub legacy: bool,
+    // What the columns of the locations of tokens count
+    pub column_unit: ColumnUnit,
+}
+
+#[derive(Debug)]
+pub struct Tokenizer<S: Source = String> {
     // Won't be able to be an owned pointer, since will be shared across tasks
-    pub input: Arc<String>,
+    pub input: Arc<S>,
     pub length: usize,
     pub position: usize,
     pub line: usize,
     pub last_line_start: usize,
+    // Maps locations back to the input before preprocessing, if that changed
+    // anything
+    pub original: Option<OffsetMap>,
+    pub options: TokenizerOptions,
+    // Parse errors found so far, for the caller to take
+    pub diagnostics: Vec<Diagnostic>,
+    // The line start, offset and column in `options.column_unit` of the last
+    // token, to count the next column on the same line from
+    column_cache: (usize, usize, usize),
+    // Tokens lexed by `peek_nth` but not returned yet, each with the state
+    // before it
+    peeked: VecDeque<(Node, TokenizerState)>,
+}
+
+/**
+ * Where a `Tokenizer` is in its input, as returned by `Tokenizer::checkpoint`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TokenizerState {
+    pub position: usize,
+    pub line: usize,
+    pub last_line_start: usize,
+    // Number of diagnostics recorded so far
+    pub diagnostics: usize,
+}
+
+impl TokenizerState {
+    /**
+     * The state at `position`, counting it as the start of line 1. Meant for
+     * starting to lex in the middle of an input, when the line does not
+     * matter or is fixed up later.
+     */
+    pub fn at_line_start(position: usize) -> TokenizerState {
+        TokenizerState {
+            position,
+            line: 1,
+            last_line_start: position,
+            diagnostics: 0,
+        }
+    }
 }
 
-impl PartialEq for NumericValue {
+impl<T: PartialEq> PartialEq for NumericValue<T> {
     fn eq(&self, other: &Self) -> bool {
-        // Compare the `representation` and `int_value` fields for exact equality.
-        self.representation == other.representation &&
-        self.int_value == other.int_value &&
-        // Compare the `value` fields with a tolerance for floating-point precision issues.
-        (self.value - other.value).abs() < f64::EPSILON
+        // `value` is compared bit for bit, like it is hashed.
+        self.representation == other.representation
+            && self.int_value == other.int_value
+            && self.value.to_bits() == other.value.to_bits()
     }
 }
 
-impl Eq for NumericValue {}
+impl<T: PartialEq> Eq for NumericValue<T> {}
+
+impl<T: Hash> Hash for NumericValue<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.representation.hash(state);
+        self.int_value.hash(state);
+        self.value.to_bits().hash(state);
+    }
+}
+
+impl<T> NumericValue<T> {
+    /**
+     * Whether the two numbers are equal up to floating point error, however
+     * they are written.
+     */
+    pub fn approx_eq(&self, other: &Self) -> bool {
+        let scale = self.value.abs().max(other.value.abs()).max(1.0);
+        (self.value - other.value).abs() <= scale * f64::EPSILON
+    }
+}
+
+impl<T: Deref<Target = str>> fmt::Display for NumericValue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.representation)
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
 
 impl SourceLocation {
     pub fn to_json(&self) -> Value {
@@ -81,20 +371,121 @@ impl SourceLocation {
     }
 }
 
-impl Tokenizer {
+/**
+ * The text of `span` in `input`, which must be the input the span came from.
+ */
+pub fn source_text(input: &str, span: Span) -> &str {
+    &input[span.start..span.end]
+}
+
+impl<S: Source> Tokenizer<S> {
     /**
      * Assumes `input` has already been preprocessed.
      */
-    pub fn new(input: Arc<String>) -> Tokenizer {
+    pub fn new(input: Arc<S>) -> Tokenizer<S> {
         Tokenizer {
             length: input.len(),
             input,
             position: 0,
             line: 1,
             last_line_start: 0,
+            original: None,
+            options: TokenizerOptions::default(),
+            diagnostics: Vec::new(),
+            column_cache: (0, 0, 1),
+            peeked: VecDeque::new(),
+        }
+    }
+
+    /**
+     * The state before the next token `next` will return, to go back to with
+     * `restore`.
+     *
+     * Peeked tokens have already been lexed, so the `position`, `line` and
+     * `last_line_start` fields are past them; the checkpoint is not.
+     */
+    pub fn checkpoint(&self) -> TokenizerState {
+        match self.peeked.front() {
+            Some((_, state)) => *state,
+            None => self.state(),
+        }
+    }
+
+    /**
+     * Go back to a checkpoint of this tokenizer, dropping the diagnostics and
+     * peeked tokens from after it.
+     */
+    pub fn restore(&mut self, checkpoint: TokenizerState) {
+        self.diagnostics.truncate(checkpoint.diagnostics);
+        self.reset_to(checkpoint);
+    }
+
+    /**
+     * Carry on lexing from `state`, which need not come from a checkpoint.
+     * `position` must be the start of a token. Peeked tokens are dropped, and
+     * diagnostics are left alone.
+     */
+    pub fn reset_to(&mut self, state: TokenizerState) {
+        self.position = state.position;
+        self.line = state.line;
+        self.last_line_start = state.last_line_start;
+        self.peeked.clear();
+    }
+
+    /**
+     * The token `next` will return, without consuming it.
+     */
+    pub fn peek(&mut self) -> Option<&Node> {
+        self.peek_nth(0)
+    }
+
+    /**
+     * The token `n` tokens after the one `next` will return, lexing and
+     * buffering the tokens up to it.
+     */
+    pub fn peek_nth(&mut self, n: usize) -> Option<&Node> {
+        while self.peeked.len() <= n {
+            let state = self.state();
+            let node = self.lex()?;
+            self.peeked.push_back((node, state));
+        }
+        self.peeked.get(n).map(|(node, _)| node)
+    }
+
+    fn state(&self) -> TokenizerState {
+        TokenizerState {
+            position: self.position,
+            line: self.line,
+            last_line_start: self.last_line_start,
+            diagnostics: self.diagnostics.len(),
         }
     }
 
+    #[inline]
+    fn lex(&mut self) -> Option<Node> {
+        let (token, location) = next_token(self)?;
+        let input: &str = &self.input;
+        let token = token.map_text(|text| match text {
+            Text::Input(start, end) => input[start..end].to_string(),
+            Text::Owned(value) => value,
+        });
+        Some((token, location))
+    }
+
+    /**
+     * The column of `offset` on the line starting at `line_start`, in the unit
+     * of the options.
+     */
+    fn unit_column(&mut self, line_start: usize, offset: usize) -> usize {
+        let (from, column) = match self.column_cache {
+            (start, from, column) if start == line_start && from <= offset => (from, column),
+            _ => (line_start, 1),
+        };
+        let column = column + self.options.column_unit.measure(&self.input[from..offset]);
+        self.column_cache = (line_start, offset, column);
+        column
+    }
+
     #[inline]
     fn is_eof(&self) -> bool {
         self.position >= self.length
@@ -139,6 +530,19 @@ impl Tokenizer {
         self.last_line_start = self.position;
     }
 
+    // Record a parse error for the bytes from `start` to `end`
+    #[cold]
+    fn report(&mut self, code: DiagnosticCode, start: usize, end: usize) {
+        let span = match &self.original {
+            Some(map) => Span {
+                start: map.original_offset(start),
+                end: map.original_offset(end),
+            },
+            None => Span { start, end },
+        };
+        self.diagnostics.push(Diagnostic::new(code, span));
+    }
+
     // Checks whether the Tokenizer has at least `num` characters remaining
     #[inline]
     fn has_more(&self, num: usize) -> bool {
@@ -146,34 +550,267 @@ impl Tokenizer {
     }
 }
 
+/**
+ * Preprocess and tokenize `input`. Locations are in terms of `input` itself
+ * rather than the preprocessed text.
+ */
 pub fn tokenize(input: &str) -> Tokenizer {
-    let input = preprocess(input);
+    tokenize_with_options(input, TokenizerOptions::default())
+}
+
+/**
+ * Like `tokenize`, with the given options.
+ */
+pub fn tokenize_with_options(input: &str, options: TokenizerOptions) -> Tokenizer {
+    let (input, map) = preprocess_with_map(input);
     Tokenizer {
         length: input.len(),
         input: Arc::new(input),
         position: 0,
         line: 1,
         last_line_start: 0,
+        original: (!map.is_identity()).then_some(map),
+        options,
+        diagnostics: Vec::new(),
+        column_cache: (0, 0, 1),
+        peeked: VecDeque::new(),
+    }
+}
+
+/**
+ * Tokenize `input` without copying string values that contain no escapes.
+ * Assumes `input` has already been preprocessed.
+ */
+pub fn tokenize_borrowed(input: &str) -> BorrowedTokenizer<'_> {
+    BorrowedTokenizer {
+        tokenizer: Tokenizer::new(Arc::new(input)),
     }
 }
 
-impl Iterator for Tokenizer {
+impl<S: Source> Iterator for Tokenizer<S> {
     type Item = Node;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        next_token(self)
+        match self.peeked.pop_front() {
+            Some((node, _)) => Some(node),
+            None => self.lex(),
+        }
+    }
+}
+
+impl<'a> Iterator for BorrowedTokenizer<'a> {
+    type Item = BorrowedNode<'a>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(((token, location), _)) = self.tokenizer.peeked.pop_front() {
+            return Some((token.map_text(Cow::Owned), location));
+        }
+        let (token, location) = next_token(&mut self.tokenizer)?;
+        let input: &'a str = *self.tokenizer.input;
+        let token = token.map_text(|text| match text {
+            Text::Input(start, end) => Cow::Borrowed(&input[start..end]),
+            Text::Owned(value) => Cow::Owned(value),
+        });
+        Some((token, location))
     }
 }
 
 // From http://dev.w3.org/csswg/css-syntax/#input-preprocessing
+//
+// Done in a single pass, copying the runs between characters that need
+// replacing. A `\r\n` pair must not be split across two calls, or it would
+// become two newlines.
 pub fn preprocess(input: &str) -> String {
-    // TODO: Is this faster if done in one pass?
+    preprocess_with_map(input).0
+}
+
+/**
+ * Offsets in preprocessed text along with where they came from in the
+ * original text.
+ *
+ * Preprocessing never changes the number of lines, only the length of some of
+ * them, so only offsets and columns need mapping.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OffsetMap {
+    // `(offset, original offset)` pairs wherever the distance between the two
+    // changes, so an offset maps through the last anchor at or before it
+    anchors: Vec<(usize, usize)>,
+}
+
+impl OffsetMap {
+    /**
+     * A map for text whose start is at `original` in the original text.
+     */
+    pub fn new(original: usize) -> OffsetMap {
+        OffsetMap {
+            anchors: vec![(0, original)],
+        }
+    }
+
+    /**
+     * Record that the text from `offset` on comes from the original text from
+     * `original` on. Anchors must be added in order.
+     */
+    pub fn anchor(&mut self, offset: usize, original: usize) {
+        let (last, last_original) = *self.anchors.last().unwrap();
+        if offset - last != original - last_original {
+            self.anchors.push((offset, original));
+        }
+    }
+
+    /**
+     * Append the map of a piece of text preprocessed on its own, which starts
+     * at `offset` in the text and at `original` in the original text.
+     */
+    pub fn append(&mut self, piece: &OffsetMap, offset: usize, original: usize) {
+        for &(piece_offset, piece_original) in &piece.anchors {
+            self.anchor(offset + piece_offset, original + piece_original);
+        }
+    }
+
+    /**
+     * Whether every offset maps to itself.
+     */
+    pub fn is_identity(&self) -> bool {
+        self.anchors
+            .iter()
+            .all(|&(offset, original)| offset == original)
+    }
+
+    pub fn original_offset(&self, offset: usize) -> usize {
+        let idx = self.anchors.partition_point(|&(start, _)| start <= offset) - 1;
+        let (start, original) = self.anchors[idx];
+        original + offset - start
+    }
+
+    pub fn original_location(&self, location: &SourceLocation) -> SourceLocation {
+        let line_start = location.span.start + 1 - location.column;
+        let start = self.original_offset(location.span.start);
+        SourceLocation {
+            line: location.line,
+            column: start - self.original_offset(line_start) + 1,
+            span: Span {
+                start,
+                end: self.original_offset(location.span.end),
+            },
+        }
+    }
+
+    /**
+     * Map the locations of `nodes`, tokens of the preprocessed text, back to
+     * the original text.
+     */
+    pub fn map_nodes(&self, nodes: &mut [Node]) {
+        if self.is_identity() {
+            return;
+        }
+        for (_, location) in nodes {
+            *location = self.original_location(location);
+        }
+    }
+}
+
+/**
+ * The replacement `preprocess` makes at `position`, if any, and how many bytes
+ * it replaces.
+ */
+#[inline]
+fn replacement_at(bytes: &[u8], position: usize) -> Option<(&'static str, usize)> {
+    match bytes[position] {
+        b'\r' if bytes.get(position + 1) == Some(&b'\n') => Some(("\n", 2)),
+        b'\r' | b'\x0C' => Some(("\n", 1)),
+        b'\x00' => Some(("\u{FFFD}", 1)),
+        _ => None,
+    }
+}
+
+/**
+ * Like `preprocess`, also returning where each offset of the result came from
+ * in `input`.
+ */
+pub fn preprocess_with_map(input: &str) -> (String, OffsetMap) {
+    let bytes = input.as_bytes();
+    let mut result = String::with_capacity(input.len());
+    let mut map = OffsetMap::new(0);
+    let mut run_start = 0;
+    let mut position = 0;
+    while position < bytes.len() {
+        let (replacement, skip) = match replacement_at(bytes, position) {
+            Some(replacement) => replacement,
+            None => {
+                position += 1;
+                continue;
+            }
+        };
+        result.push_str(&input[run_start..position]);
+        result.push_str(replacement);
+        position += skip;
+        run_start = position;
+        map.anchor(result.len(), position);
+    }
+    result.push_str(&input[run_start..]);
+    (result, map)
+}
+
+/**
+ * The length in bytes of `preprocess(input)`, without building it.
+ */
+pub fn preprocessed_len(input: &str) -> usize {
+    let bytes = input.as_bytes();
+    let mut len = bytes.len();
+    let mut position = 0;
+    while position < bytes.len() {
+        match replacement_at(bytes, position) {
+            Some((replacement, skip)) => {
+                len = len - skip + replacement.len();
+                position += skip;
+            }
+            None => position += 1,
+        }
+    }
+    len
+}
+
+/**
+ * Like `preprocess_with_map`, writing the result into `out`, which must be
+ * exactly `preprocessed_len(input)` bytes long. This lets the pieces of a
+ * larger text be preprocessed in parallel into one buffer.
+ */
+pub fn preprocess_into(input: &str, out: &mut [u8]) -> OffsetMap {
+    let bytes = input.as_bytes();
+    let mut map = OffsetMap::new(0);
+    let mut written = 0;
+    let mut run_start = 0;
+    let mut position = 0;
+    while position < bytes.len() {
+        let (replacement, skip) = match replacement_at(bytes, position) {
+            Some(replacement) => replacement,
+            None => {
+                position += 1;
+                continue;
+            }
+        };
+        for text in [&bytes[run_start..position], replacement.as_bytes()] {
+            out[written..written + text.len()].copy_from_slice(text);
+            written += text.len();
+        }
+        position += skip;
+        run_start = position;
+        map.anchor(written, position);
+    }
+    out[written..].copy_from_slice(&bytes[run_start..]);
+    map
+}
+
+// Whether `preprocess` would change `input` at all.
+#[inline]
+pub fn needs_preprocessing(input: &str) -> bool {
     input
-        .replace("\r\n", "\n")
-        .replace("\r", "\n")
-        .replace('\x0C', "\n")
-        .replace('\x00', "\u{FFFD}")
+        .bytes()
+        .any(|b| matches!(b, b'\r' | b'\x0C' | b'\x00'))
 }
 
 macro_rules! is_match {
@@ -183,19 +820,27 @@ macro_rules! is_match {
 }
 
 // From http://dev.w3.org/csswg/css-syntax/#consume-a-token
-fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
-    consume_comments(tokenizer);
+fn next_token<S: Source>(tokenizer: &mut Tokenizer<S>) -> Option<(RawToken, SourceLocation)> {
+    let lossless = tokenizer.options.lossless;
+    let legacy = tokenizer.options.legacy;
+    if !lossless {
+        consume_comments(tokenizer);
+    }
     if tokenizer.is_eof() {
         return None;
     }
-    let start_location = SourceLocation {
-        line: tokenizer.line,
-        // The start of the line is column 1:
-        column: tokenizer.position - tokenizer.last_line_start + 1,
-    };
+    let start = tokenizer.position;
+    let line = tokenizer.line;
+    let line_start = tokenizer.last_line_start;
+    // The start of the line is column 1:
+    let column = start - line_start + 1;
     let c = tokenizer.current_char();
 
     let token = match c {
+        '/' if lossless && tokenizer.starts_with("/*") => {
+            consume_comment(tokenizer);
+            Token::Comment(Text::Input(start, tokenizer.position))
+        }
         '\t' | '\n' | ' ' => {
             while !tokenizer.is_eof() {
                 match tokenizer.current_char() {
@@ -207,7 +852,7 @@ fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
                     _ => break,
                 }
             }
-            Token::WhiteSpace
+            Token::WhiteSpace(lossless.then_some(Text::Input(start, tokenizer.position)))
         }
         '\"' => consume_string(tokenizer, false),
         '#' => {
@@ -228,7 +873,7 @@ fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
             }
         }
         '$' => {
-            if tokenizer.starts_with("$=") {
+            if legacy && tokenizer.starts_with("$=") {
                 tokenizer.position += 2;
                 Token::SuffixMatch
             } else {
@@ -246,7 +891,7 @@ fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
             Token::RightParen
         }
         '*' => {
-            if tokenizer.starts_with("*=") {
+            if legacy && tokenizer.starts_with("*=") {
                 tokenizer.position += 2;
                 Token::SubstringMatch
             } else {
@@ -277,11 +922,11 @@ fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
                     && is_match!(tokenizer.char_at(2), '0'..='9'))
             {
                 consume_numeric(tokenizer)
-            } else if is_ident_start(tokenizer) {
-                consume_ident_like(tokenizer)
             } else if tokenizer.starts_with("-->") {
                 tokenizer.position += 3;
                 Token::CDC
+            } else if is_ident_start(tokenizer) {
+                consume_ident_like(tokenizer)
             } else {
                 tokenizer.position += 1;
                 Token::Delim(c)
@@ -331,6 +976,7 @@ fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
             if !tokenizer.starts_with("\\\n") {
                 consume_ident_like(tokenizer)
             } else {
+                tokenizer.report(DiagnosticCode::InvalidEscape, start, start + 1);
                 tokenizer.position += 1;
                 Token::Delim(c)
             }
@@ -340,7 +986,7 @@ fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
             Token::RightBracket
         }
         '^' => {
-            if tokenizer.starts_with("^=") {
+            if legacy && tokenizer.starts_with("^=") {
                 tokenizer.position += 2;
                 Token::PrefixMatch
             } else {
@@ -359,7 +1005,8 @@ fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
         '0'..='9' => consume_numeric(tokenizer),
 
         'u' | 'U' => {
-            if tokenizer.has_more(2)
+            if legacy
+                && tokenizer.has_more(2)
                 && tokenizer.char_at(1) == '+'
                 && is_match!(tokenizer.char_at(2), '0'..='9' | 'a'..='f' | 'A'..='F' | '?')
             {
@@ -373,10 +1020,10 @@ fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
         'a'..='z' | 'A'..='Z' | '_' => consume_ident_like(tokenizer),
 
         '|' => {
-            if tokenizer.starts_with("|=") {
+            if legacy && tokenizer.starts_with("|=") {
                 tokenizer.position += 2;
                 Token::DashMatch
-            } else if tokenizer.starts_with("||") {
+            } else if legacy && tokenizer.starts_with("||") {
                 tokenizer.position += 2;
                 Token::Column
             } else {
@@ -386,7 +1033,7 @@ fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
         }
 
         '~' => {
-            if tokenizer.starts_with("~=") {
+            if legacy && tokenizer.starts_with("~=") {
                 tokenizer.position += 2;
                 Token::IncludeMatch
             } else {
@@ -402,30 +1049,54 @@ fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
             Token::Delim(c)
         }
     };
-    Some((token, start_location))
+    let location = SourceLocation {
+        line,
+        column,
+        span: Span {
+            start,
+            end: tokenizer.position,
+        },
+    };
+    let mut location = match &tokenizer.original {
+        Some(map) => map.original_location(&location),
+        None => location,
+    };
+    // Preprocessing only replaces characters within a line by a single one, so
+    // counting other units in the preprocessed text gives the same column as
+    // in the original.
+    if tokenizer.options.column_unit != ColumnUnit::Utf8 {
+        location.column = tokenizer.unit_column(line_start, start);
+    }
+    Some((token, location))
 }
 
 #[inline]
-fn consume_comments(tokenizer: &mut Tokenizer) {
+fn consume_comments<S: Source>(tokenizer: &mut Tokenizer<S>) {
     while tokenizer.starts_with("/*") {
-        tokenizer.position += 2; // +2 to consume "/*"
-        while !tokenizer.is_eof() {
-            match tokenizer.consume_char() {
-                '*' => {
-                    if !tokenizer.is_eof() && tokenizer.current_char() == '/' {
-                        tokenizer.position += 1;
-                        break;
-                    }
-                }
-                '\n' => tokenizer.new_line(),
-                _ => (),
+        consume_comment(tokenizer);
+    }
+}
+
+// Assumes the input starts with "/*"
+#[inline]
+fn consume_comment<S: Source>(tokenizer: &mut Tokenizer<S>) {
+    let start = tokenizer.position;
+    tokenizer.position += 2; // +2 to consume "/*"
+    while !tokenizer.is_eof() {
+        match tokenizer.consume_char() {
+            '*' if !tokenizer.is_eof() && tokenizer.current_char() == '/' => {
+                tokenizer.position += 1;
+                return;
             }
+            '\n' => tokenizer.new_line(),
+            _ => (),
         }
     }
+    tokenizer.report(DiagnosticCode::UnterminatedComment, start, tokenizer.length);
 }
 
 // From http://dev.w3.org/csswg/css-syntax/#consume-a-string-token0
-fn consume_string(tokenizer: &mut Tokenizer, single_quote: bool) -> Token {
+fn consume_string<S: Source>(tokenizer: &mut Tokenizer<S>, single_quote: bool) -> RawToken {
     match consume_quoted_string(tokenizer, single_quote) {
         Some(value) => Token::String(value),
         None => Token::BadString,
@@ -433,15 +1104,21 @@ fn consume_string(tokenizer: &mut Tokenizer, single_quote: bool) -> Token {
 }
 
 // Return None on syntax error (ie. unescaped newline)
-fn consume_quoted_string(tokenizer: &mut Tokenizer, single_quote: bool) -> Option<String> {
+fn consume_quoted_string<S: Source>(
+    tokenizer: &mut Tokenizer<S>,
+    single_quote: bool,
+) -> Option<Text> {
+    let start = tokenizer.position;
     tokenizer.position += 1; // Skip the initial quote
-    let mut string: String = String::new();
+    let mut string = TextBuilder::new(tokenizer.position);
     while !tokenizer.is_eof() {
         match tokenizer.consume_char() {
-            '\"' if !single_quote => break,
-            '\'' if single_quote => break,
+            '\"' if !single_quote => return Some(string.finish()),
+            '\'' if single_quote => return Some(string.finish()),
             '\n' => {
                 tokenizer.position -= 1;
+                let position = tokenizer.position;
+                tokenizer.report(DiagnosticCode::NewlineInString, position, position + 1);
                 return None;
             }
             '\\' => {
@@ -450,20 +1127,25 @@ fn consume_quoted_string(tokenizer: &mut Tokenizer, single_quote: bool) -> Optio
                         // Escaped newline
                         tokenizer.position += 1;
                         tokenizer.new_line();
+                        string.owned(&tokenizer.input);
                     } else {
-                        string.push(consume_escape(tokenizer))
+                        let c = consume_escape(tokenizer);
+                        string.owned(&tokenizer.input).push(c)
                     }
+                } else {
+                    // Escaped EOF, do nothing.
+                    string.owned(&tokenizer.input);
                 }
-                // else: escaped EOF, do nothing.
             }
-            c => string.push(c),
+            c => string.push_input(c, tokenizer.position),
         }
     }
-    Some(string)
+    tokenizer.report(DiagnosticCode::UnterminatedString, start, tokenizer.length);
+    Some(string.finish())
 }
 
 #[inline]
-fn is_ident_start(tokenizer: &mut Tokenizer) -> bool {
+fn is_ident_start<S: Source>(tokenizer: &mut Tokenizer<S>) -> bool {
     !tokenizer.is_eof()
         && match tokenizer.current_char() {
             'a'..='z' | 'A'..='Z' | '_' => true,
@@ -471,6 +1153,7 @@ fn is_ident_start(tokenizer: &mut Tokenizer) -> bool {
                 tokenizer.has_more(1)
                     && match tokenizer.char_at(1) {
                         'a'..='z' | 'A'..='Z' | '_' => true,
+                        '-' => !tokenizer.options.legacy,
                         '\\' => !tokenizer.input[(tokenizer.position + 1)..].starts_with("\\\n"),
                         c => c > '\x7F', // Non-ASCII
                     }
@@ -483,15 +1166,18 @@ fn is_ident_start(tokenizer: &mut Tokenizer) -> bool {
 // Consume an identifier-like token.
 //
 // From http://dev.w3.org/csswg/css-syntax/#consume-an-ident-like-token
-fn consume_ident_like(tokenizer: &mut Tokenizer) -> Token {
+fn consume_ident_like<S: Source>(tokenizer: &mut Tokenizer<S>) -> RawToken {
+    let start = tokenizer.position;
     let value = consume_name(tokenizer);
 
     if !tokenizer.is_eof() && tokenizer.current_char() == '\x28' {
         // \x28 == (
         tokenizer.position += 1;
 
-        if value.eq_ignore_ascii_case("url") {
-            consume_url(tokenizer)
+        if !value.as_str(&tokenizer.input).eq_ignore_ascii_case("url") {
+            Token::Function(value)
+        } else if tokenizer.options.legacy || !starts_quoted_url(tokenizer) {
+            consume_url(tokenizer, start)
         } else {
             Token::Function(value)
         }
@@ -503,46 +1189,48 @@ fn consume_ident_like(tokenizer: &mut Tokenizer) -> Token {
 // Consume a name
 //
 // From http://dev.w3.org/csswg/css-syntax/#consume-a-name
-fn consume_name(tokenizer: &mut Tokenizer) -> String {
-    let mut value = String::new();
+fn consume_name<S: Source>(tokenizer: &mut Tokenizer<S>) -> Text {
+    let mut value = TextBuilder::new(tokenizer.position);
     while !tokenizer.is_eof() {
         let c = tokenizer.current_char();
-        value.push(match c {
+        match c {
             'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => {
                 tokenizer.position += 1;
-                c
+                value.push_input(c, tokenizer.position);
             }
             '\\' => {
                 if tokenizer.starts_with("\\\n") {
                     break;
                 }
                 tokenizer.position += 1;
-                consume_escape(tokenizer)
+                let c = consume_escape(tokenizer);
+                value.owned(&tokenizer.input).push(c);
             }
             _ => {
                 if c > '\x7F' {
-                    tokenizer.consume_char()
+                    tokenizer.consume_char();
+                    value.push_input(c, tokenizer.position);
                 } else {
                     break;
                 }
             }
-        })
+        }
     }
 
-    value
+    value.finish()
 }
 
-fn consume_numeric(tokenizer: &mut Tokenizer) -> Token {
+fn consume_numeric<S: Source>(tokenizer: &mut Tokenizer<S>) -> RawToken {
     // Parse [+-]?\d*(\.\d+)?([eE][+-]?\d+)?
     // But this is always called so that there is at least one digit in \d*(\.\d+)?
-    let mut representation = String::new();
+    let start = tokenizer.position;
     let mut is_integer = true;
     if is_match!(tokenizer.current_char(), '-' | '+') {
-        representation.push(tokenizer.consume_char())
+        tokenizer.position += 1;
     }
     while !tokenizer.is_eof() {
         match tokenizer.current_char() {
-            '0'..='9' => representation.push(tokenizer.consume_char()),
+            '0'..='9' => tokenizer.position += 1,
             _ => break,
         }
     }
@@ -551,11 +1239,11 @@ fn consume_numeric(tokenizer: &mut Tokenizer) -> Token {
         && is_match!(tokenizer.char_at(1), '0'..='9')
     {
         is_integer = false;
-        representation.push(tokenizer.consume_char()); // '.'
-        representation.push(tokenizer.consume_char()); // digit
+        tokenizer.position += 1; // '.'
+        tokenizer.position += 1; // digit
         while !tokenizer.is_eof() {
             match tokenizer.current_char() {
-                '0'..='9' => representation.push(tokenizer.consume_char()),
+                '0'..='9' => tokenizer.position += 1,
                 _ => break,
             }
         }
@@ -569,34 +1257,35 @@ fn consume_numeric(tokenizer: &mut Tokenizer) -> Token {
             && is_match!(tokenizer.char_at(2), '0'..='9'))
     {
         is_integer = false;
-        representation.push(tokenizer.consume_char()); // 'e' or 'E'
-        representation.push(tokenizer.consume_char()); // sign or digit
-                                                       // If the above was a sign, the first digit it consumed below
-                                                       // and we make one extraneous is_eof() check.
+        tokenizer.position += 1; // 'e' or 'E'
+        tokenizer.position += 1; // sign or digit
+                                 // If the above was a sign, the first digit it consumed below
+                                 // and we make one extraneous is_eof() check.
         while !tokenizer.is_eof() {
             match tokenizer.current_char() {
-                '0'..='9' => representation.push(tokenizer.consume_char()),
+                '0'..='9' => tokenizer.position += 1,
                 _ => break,
             }
         }
     }
 
+    let end = tokenizer.position;
+    let representation = &tokenizer.input[start..end];
+    // Values out of range are clamped rather than becoming infinite or
+    // overflowing.
+    let int_value = is_integer.then(|| {
+        representation.trim_start_matches('+').parse().unwrap_or(
+            if representation.starts_with('-') {
+                i64::MIN
+            } else {
+                i64::MAX
+            },
+        )
+    });
     let value = NumericValue {
-        int_value: if is_integer {
-            Some(
-                // Remove any + sign as int::from_str() does not parse them.
-                if !representation.starts_with('+') {
-                    i64::from_str(&representation)
-                } else {
-                    i64::from_str(&representation[1..])
-                }
-                .unwrap(),
-            )
-        } else {
-            None
-        },
-        value: f64::from_str(&representation).unwrap(),
-        representation,
+        int_value,
+        value: f64::from_str(representation).map_or(0.0, |value| value.clamp(f64::MIN, f64::MAX)),
+        representation: Text::Input(start, end),
     };
     if !tokenizer.is_eof() && tokenizer.current_char() == '%' {
         tokenizer.position += 1;
@@ -608,10 +1297,31 @@ fn consume_numeric(tokenizer: &mut Tokenizer) -> Token {
     }
 }
 
+// Whether a quoted string follows "url(", in which case it is a function
+// rather than a url token. Consumes all but the last whitespace before it.
+//
+// From https://www.w3.org/TR/css-syntax-3/#consume-ident-like-token
+fn starts_quoted_url<S: Source>(tokenizer: &mut Tokenizer<S>) -> bool {
+    while tokenizer.has_more(1)
+        && is_match!(tokenizer.current_char(), ' ' | '\t' | '\n')
+        && is_match!(tokenizer.char_at(1), ' ' | '\t' | '\n')
+    {
+        if tokenizer.consume_char() == '\n' {
+            tokenizer.new_line();
+        }
+    }
+    let mut chars = tokenizer.input[tokenizer.position..].chars();
+    match chars.next() {
+        Some('\"' | '\'') => true,
+        Some(' ' | '\t' | '\n') => matches!(chars.next(), Some('\"' | '\'')),
+        _ => false,
+    }
+}
+
 // Consume a URL. Assumes that the initial "url(" has already been consumed
 //
 // From http://dev.w3.org/csswg/css-syntax/#consume-a-url-token0
-fn consume_url(tokenizer: &mut Tokenizer) -> Token {
+fn consume_url<S: Source>(tokenizer: &mut Tokenizer<S>, start: usize) -> RawToken {
     while !tokenizer.is_eof() {
         match tokenizer.current_char() {
             '\t' | ' ' => tokenizer.position += 1,
@@ -619,70 +1329,90 @@ fn consume_url(tokenizer: &mut Tokenizer) -> Token {
                 tokenizer.position += 1;
                 tokenizer.new_line();
             }
-            '\"' => return consume_quoted_url(tokenizer, false),
-            '\'' => return consume_quoted_url(tokenizer, true),
+            '\"' => return consume_quoted_url(tokenizer, start, false),
+            '\'' => return consume_quoted_url(tokenizer, start, true),
             // '\x29' == ')'
             '\x29' => {
                 tokenizer.position += 1;
-                break;
+                return Token::URL(Text::Owned(String::new()));
             }
-            _ => return consume_unquoted_url(tokenizer),
+            _ => return consume_unquoted_url(tokenizer, start),
         }
     }
-    return Token::URL(String::new());
+    tokenizer.report(DiagnosticCode::UnterminatedUrl, start, tokenizer.length);
+    return Token::URL(Text::Owned(String::new()));
 
-    fn consume_quoted_url(tokenizer: &mut Tokenizer, single_quote: bool) -> Token {
+    fn consume_quoted_url<S: Source>(
+        tokenizer: &mut Tokenizer<S>,
+        start: usize,
+        single_quote: bool,
+    ) -> RawToken {
         match consume_quoted_string(tokenizer, single_quote) {
-            Some(value) => consume_url_end(tokenizer, value),
+            Some(value) => consume_url_end(tokenizer, start, value),
             None => consume_bad_url(tokenizer),
         }
     }
 
-    fn consume_unquoted_url(tokenizer: &mut Tokenizer) -> Token {
-        let mut string = String::new();
+    fn consume_unquoted_url<S: Source>(tokenizer: &mut Tokenizer<S>, start: usize) -> RawToken {
+        let mut string = TextBuilder::new(tokenizer.position);
         while !tokenizer.is_eof() {
-            let next_char = match tokenizer.consume_char() {
-                ' ' | '\t' => return consume_url_end(tokenizer, string),
+            match tokenizer.consume_char() {
+                ' ' | '\t' => return consume_url_end(tokenizer, start, string.finish()),
                 '\n' => {
                     tokenizer.new_line();
-                    return consume_url_end(tokenizer, string)
+                    return consume_url_end(tokenizer, start, string.finish())
                 },
                 // '\x29' == ')'
-                '\x29' => break,
-                '\x00'..='\x08' | '\x0B' | '\x0E'..='\x1F' | '\x7F'  // non-printable
-                    | '\"' | '\'' | '\x28' => return consume_bad_url(tokenizer),
+                '\x29' => return Token::URL(string.finish()),
+                c @ ('\x00'..='\x08' | '\x0B' | '\x0E'..='\x1F' | '\x7F'  // non-printable
+                    | '\"' | '\'' | '\x28') => {
+                    let position = tokenizer.position;
+                    tokenizer.report(DiagnosticCode::BadUrlCharacter, position - c.len_utf8(), position);
+                    return consume_bad_url(tokenizer)
+                },
                 '\\' => {
 
                     if !tokenizer.is_eof() && tokenizer.current_char() == '\n' {
+                        let position = tokenizer.position;
+                        tokenizer.report(DiagnosticCode::InvalidEscape, position - 1, position);
                         return consume_bad_url(tokenizer)
                     }
-                    consume_escape(tokenizer)
+                    let c = consume_escape(tokenizer);
+                    string.owned(&tokenizer.input).push(c)
                 },
-                c => c
-            };
-            string.push(next_char)
+                c => string.push_input(c, tokenizer.position),
+            }
         }
-        Token::URL(string)
+        tokenizer.report(DiagnosticCode::UnterminatedUrl, start, tokenizer.length);
+        Token::URL(string.finish())
     }
 
-    fn consume_url_end(tokenizer: &mut Tokenizer, string: String) -> Token {
+    fn consume_url_end<S: Source>(
+        tokenizer: &mut Tokenizer<S>,
+        start: usize,
+        string: Text,
+    ) -> RawToken {
         while !tokenizer.is_eof() {
             match tokenizer.consume_char() {
                 ' ' | '\t' => (),
                 '\n' => tokenizer.new_line(),
-                '\x29' => break,
+                '\x29' => return Token::URL(string),
                 _ => return consume_bad_url(tokenizer),
             }
         }
+        tokenizer.report(DiagnosticCode::UnterminatedUrl, start, tokenizer.length);
         Token::URL(string)
     }
 
-    fn consume_bad_url(tokenizer: &mut Tokenizer) -> Token {
+    fn consume_bad_url<S: Source>(tokenizer: &mut Tokenizer<S>) -> RawToken {
         // Consume up to the closing )
         while !tokenizer.is_eof() {
             match tokenizer.consume_char() {
                 '\x29' => break,
-                '\\' => tokenizer.position += 1, // Skip an escaped ')' or '\'
+                // Skip an escaped ')' or '\', keeping count of an escaped newline
+                '\\' if !tokenizer.is_eof() && tokenizer.consume_char() == '\n' => {
+                    tokenizer.new_line()
+                }
                 '\n' => tokenizer.new_line(),
                 _ => (),
             }
@@ -691,10 +1421,17 @@ fn consume_url(tokenizer: &mut Tokenizer) -> Token {
     }
 }
 
+// The value of at most 6 hex digits, which always fits a `u32`.
+#[inline]
+fn hex_value(hex: &str) -> u32 {
+    hex.chars()
+        .fold(0, |value, c| value * 16 + c.to_digit(16).unwrap_or(0))
+}
+
 // Assumes the initial "u+" has already been consumed
 //
 // From http://dev.w3.org/csswg/css-syntax/#consume-a-unicode-range-token0
-fn consume_unicode_range(tokenizer: &mut Tokenizer) -> Token {
+fn consume_unicode_range<S: Source>(tokenizer: &mut Tokenizer<S>) -> RawToken {
     let mut hex = String::new();
     while hex.len() < 6
         && !tokenizer.is_eof()
@@ -714,10 +1451,10 @@ fn consume_unicode_range(tokenizer: &mut Tokenizer) -> Token {
     let start;
     let end;
     if question_marks > 0 {
-        start = u32::from_str_radix(&(hex.clone() + &"0".repeat(question_marks)), 16).unwrap();
-        end = u32::from_str_radix(&(hex + &"F".repeat(question_marks)), 16).unwrap();
+        start = hex_value(&hex) << (4 * question_marks);
+        end = start | ((1 << (4 * question_marks)) - 1);
     } else {
-        start = u32::from_str_radix(&hex, 16).unwrap();
+        start = hex_value(&hex);
         hex = String::new();
         if !tokenizer.is_eof() && tokenizer.current_char() == '-' {
             tokenizer.position += 1;
@@ -733,7 +1470,7 @@ fn consume_unicode_range(tokenizer: &mut Tokenizer) -> Token {
             }
         }
         end = if !hex.is_empty() {
-            u32::from_str_radix(&hex, 16).unwrap()
+            hex_value(&hex)
         } else {
             start
         }
@@ -744,10 +1481,12 @@ fn consume_unicode_range(tokenizer: &mut Tokenizer) -> Token {
 // Assumes that the U+005C REVERSE SOLIDUS (\) has already been consumed
 // and that the next input character has already been verified
 // to not be a newline.
-fn consume_escape(tokenizer: &mut Tokenizer) -> char {
+fn consume_escape<S: Source>(tokenizer: &mut Tokenizer<S>) -> char {
     if tokenizer.is_eof() {
+        let position = tokenizer.position;
+        tokenizer.report(DiagnosticCode::EscapedEof, position - 1, position);
         return '\u{FFFD}';
-    } // Escaped EOF
+    }
     let c = tokenizer.consume_char();
     match c {
         '0'..='9' | 'A'..='F' | 'a'..='f' => {
@@ -773,7 +1512,7 @@ fn consume_escape(tokenizer: &mut Tokenizer) -> char {
                 }
             }
             static REPLACEMENT_CHAR: char = '\u{FFFD}';
-            let c: u32 = u32::from_str_radix(&hex, 16).unwrap();
+            let c = hex_value(&hex);
             if c != 0 {
                 let c = char::from_u32(c);
                 c.unwrap_or(REPLACEMENT_CHAR)
@@ -1,5 +1,19 @@
+pub use diagnostic::*;
+pub use html::*;
+pub use incremental::*;
 pub use lexer::*;
+pub use line_index::*;
+pub use minify::*;
+pub use parser::*;
+pub use to_css::*;
 pub use to_json::*;
 
+pub mod diagnostic;
+pub mod html;
+pub mod incremental;
 pub mod lexer;
+pub mod line_index;
+pub mod minify;
+pub mod parser;
+pub mod to_css;
 pub mod to_json;
@@ -0,0 +1,114 @@
+/**
+ * What the columns of locations count. Lines and columns start at 1 whatever
+ * the unit.
+ *
+ * The names are those of the position encodings of the Language Server
+ * Protocol, which defaults to `Utf16`.
+ */
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub enum ColumnUnit {
+    // Bytes
+    #[default]
+    Utf8,
+    // UTF-16 code units, so characters outside the Basic Multilingual Plane
+    // count twice
+    Utf16,
+    // Unicode scalar values, i.e. `char`s
+    Utf32,
+}
+
+impl ColumnUnit {
+    /**
+     * The length of `text` in this unit.
+     */
+    pub fn measure(self, text: &str) -> usize {
+        match self {
+            ColumnUnit::Utf8 => text.len(),
+            ColumnUnit::Utf16 => text.chars().map(char::len_utf16).sum(),
+            ColumnUnit::Utf32 => text.chars().count(),
+        }
+    }
+
+    fn char_len(self, c: char) -> usize {
+        match self {
+            ColumnUnit::Utf8 => c.len_utf8(),
+            ColumnUnit::Utf16 => c.len_utf16(),
+            ColumnUnit::Utf32 => 1,
+        }
+    }
+}
+
+/**
+ * Converts between byte offsets into a text and lines and columns in any
+ * `ColumnUnit`.
+ *
+ * Lines end at `\n`, `\r\n` or `\r`, as in the Language Server Protocol and
+ * in editors. The tokenizer also ends lines at form feeds, as CSS does, so its
+ * lines only agree with the index on text without them; the spans of its
+ * locations can be converted with the index instead.
+ */
+pub struct LineIndex<'a> {
+    pub input: &'a str,
+    // The byte offset of every line start
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(input: &'a str) -> LineIndex<'a> {
+        let bytes = input.as_bytes();
+        let mut line_starts = vec![0];
+        for (idx, &b) in bytes.iter().enumerate() {
+            match b {
+                b'\r' if bytes.get(idx + 1) == Some(&b'\n') => (),
+                b'\n' | b'\r' => line_starts.push(idx + 1),
+                _ => (),
+            }
+        }
+        LineIndex { input, line_starts }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /**
+     * The line and column of the byte `offset`, which must be on a character
+     * boundary and at most the length of the input.
+     */
+    pub fn line_column(&self, offset: usize, unit: ColumnUnit) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[line - 1];
+        (line, unit.measure(&self.input[line_start..offset]) + 1)
+    }
+
+    /**
+     * The byte offset of `column` on `line`.
+     *
+     * Returns `None` if the line does not exist, if the column is past the end
+     * of the line, not counting its newline, or if it is in the middle of a
+     * character, such as between the two halves of a UTF-16 surrogate pair.
+     */
+    pub fn offset(&self, line: usize, column: usize, unit: ColumnUnit) -> Option<usize> {
+        let line_start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let line_end = match self.line_starts.get(line) {
+            Some(&next) => next,
+            None => self.input.len(),
+        };
+        let text = &self.input[line_start..line_end];
+        let text = text
+            .strip_suffix("\r\n")
+            .or_else(|| text.strip_suffix(['\n', '\r']))
+            .unwrap_or(text);
+        let mut units = column.checked_sub(1)?;
+        if unit == ColumnUnit::Utf8 {
+            return text.is_char_boundary(units).then_some(line_start + units);
+        }
+        for (idx, c) in text.char_indices() {
+            if units == 0 {
+                return Some(line_start + idx);
+            }
+            units = units.checked_sub(unit.char_len(c))?;
+        }
+        (units == 0).then_some(line_start + text.len())
+    }
+}
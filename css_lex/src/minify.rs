@@ -0,0 +1,240 @@
+use crate::lexer::*;
+use crate::to_css::*;
+
+/**
+ * Minify a stylesheet.
+ *
+ * Comments are dropped, whitespace is collapsed to a single space and removed
+ * entirely next to `{`, `}`, `;`, `,`, `:`, brackets and `>`, and the last `;`
+ * of a block is dropped. In declaration values numbers lose redundant zeros
+ * and colours like `#aabbcc` are shortened to `#abc`.
+ */
+pub fn minify(input: &str) -> String {
+    let tokens: Vec<Node> = tokenize(input).collect();
+    minify_tokens(&tokens)
+}
+
+/**
+ * Minify `tokens`, which must start at the top level of a stylesheet.
+ *
+ * Runs of whole rules, such as the chunks of `spec_tokenize_rules` in
+ * `spec_css`, can be minified separately and the results concatenated.
+ */
+pub fn minify_tokens(tokens: &[Node]) -> String {
+    minify_at_depth(tokens, 0)
+}
+
+/**
+ * Minify `tokens` as the contents of a block, such as a `style` attribute.
+ */
+pub fn minify_declaration_list(tokens: &[Node]) -> String {
+    minify_at_depth(tokens, 1)
+}
+
+fn minify_at_depth(tokens: &[Node], mut depth: usize) -> String {
+    let mut writer = Writer::default();
+    let mut start = 0;
+    for (end, (token, _)) in tokens.iter().enumerate() {
+        let in_block = depth > 0;
+        match token {
+            Token::LeftCurlyBracket => depth += 1,
+            Token::RightCurlyBracket => depth = depth.saturating_sub(1),
+            Token::Semicolon => (),
+            _ => continue,
+        }
+        writer.statement(
+            &tokens[start..end],
+            in_block && *token != Token::LeftCurlyBracket,
+        );
+        writer.write(token, false);
+        start = end + 1;
+    }
+    writer.statement(&tokens[start..], depth > 0);
+    writer.out
+}
+
+#[derive(Default)]
+struct Writer<'a> {
+    out: String,
+    // The last token written
+    last: Option<&'a Token>,
+    // A whitespace token was seen since the last token written
+    space: bool,
+    // A `;` was seen and is only written if something other than `}` follows
+    semicolon: bool,
+    // The last token written ended with a newline, which stands in for the
+    // whitespace token that follows it
+    newline: bool,
+}
+
+impl<'a> Writer<'a> {
+    /**
+     * Write the tokens of one statement, up to but not including the `{`, `}`
+     * or `;` ending it. If it may be a declaration and starts with a property
+     * name and a colon, the tokens after the colon are a value.
+     */
+    fn statement(&mut self, tokens: &'a [Node], may_be_declaration: bool) {
+        let value_start = if may_be_declaration {
+            declaration_value_start(tokens)
+        } else {
+            None
+        };
+        for (idx, (token, _)) in tokens.iter().enumerate() {
+            let in_value = value_start.is_some_and(|start| idx >= start);
+            self.write(token, in_value);
+        }
+    }
+
+    fn write(&mut self, token: &'a Token, in_value: bool) {
+        match token {
+            Token::Comment(_) => (),
+            Token::WhiteSpace(_) if self.newline => self.newline = false,
+            Token::WhiteSpace(_) => self.space = true,
+            Token::Semicolon => {
+                self.semicolon = true;
+                self.space = false;
+            }
+            _ => {
+                if self.semicolon && *token != Token::RightCurlyBracket {
+                    self.out.push(';');
+                    self.space = false;
+                    self.last = None;
+                }
+                self.semicolon = false;
+                if self.space && !self.out.is_empty() && self.needs_space_before(token) {
+                    self.out.push(' ');
+                } else if self.last.is_some_and(|last| would_merge(last, token)) {
+                    self.out.push_str("/**/");
+                }
+                self.last = Some(token);
+                self.space = false;
+                self.newline = false;
+                write_token(&mut self.out, token, in_value);
+                if matches!(token, Token::BadString | Token::Delim('\\')) {
+                    self.newline = true;
+                }
+            }
+        }
+    }
+
+    fn needs_space_before(&self, token: &Token) -> bool {
+        let after = !self.out.ends_with(['{', '}', ';', ',', ':', '(', '[', '>']);
+        let before = match token {
+            Token::LeftCurlyBracket
+            | Token::RightCurlyBracket
+            | Token::Comma
+            | Token::RightParen
+            | Token::RightBracket => false,
+            // `- >` would become `->`, and `-- >` the `-->` token
+            Token::Delim('>') => self.out.ends_with('-'),
+            _ => true,
+        };
+        after && before
+    }
+}
+
+/**
+ * If `tokens` start with a property name and a colon, the index just after the
+ * colon. Custom properties are left alone, as their value is kept as written.
+ */
+fn declaration_value_start(tokens: &[Node]) -> Option<usize> {
+    let mut significant = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, (token, _))| !matches!(token, Token::WhiteSpace(_) | Token::Comment(_)));
+    match significant.next() {
+        Some((_, (Token::Ident(name), _))) if !name.starts_with("--") => (),
+        _ => return None,
+    }
+    match significant.next() {
+        Some((idx, (Token::Colon, _))) => Some(idx + 1),
+        _ => None,
+    }
+}
+
+/**
+ * Drop a leading `+`, leading zeros of the integer part and trailing zeros of
+ * the fraction. A number with a fraction keeps at least one digit of it, so
+ * that it is not turned into an integer.
+ */
+fn shorten_number(representation: &str) -> String {
+    let (sign, rest) = match representation.strip_prefix('+') {
+        Some(rest) => ("", rest),
+        None => match representation.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", representation),
+        },
+    };
+    let (mantissa, exponent) = match rest.find(['e', 'E']) {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, ""),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+
+    let integer = integer.trim_start_matches('0');
+    let mut result = sign.to_string();
+    match fraction {
+        Some(fraction) => {
+            let fraction = fraction.trim_end_matches('0');
+            result.push_str(integer);
+            result.push('.');
+            result.push_str(if fraction.is_empty() { "0" } else { fraction });
+        }
+        None if integer.is_empty() => result.push('0'),
+        None => result.push_str(integer),
+    }
+    result.push_str(exponent);
+    result
+}
+
+/**
+ * Shorten a six or eight digit hex colour whose digits come in pairs.
+ */
+fn shorten_color(name: &str) -> Option<String> {
+    let bytes = name.as_bytes();
+    if !(bytes.len() == 6 || bytes.len() == 8) || !bytes.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let pairs = bytes.chunks(2);
+    if !pairs
+        .clone()
+        .all(|pair| pair[0].eq_ignore_ascii_case(&pair[1]))
+    {
+        return None;
+    }
+    Some(
+        pairs
+            .map(|pair| pair[0].to_ascii_lowercase() as char)
+            .collect(),
+    )
+}
+
+fn write_token(out: &mut String, token: &Token, in_value: bool) {
+    if in_value {
+        match token {
+            Token::Number(value) => return out.push_str(&shorten_number(&value.representation)),
+            Token::Percentage(value) => {
+                out.push_str(&shorten_number(&value.representation));
+                out.push('%');
+                return;
+            }
+            Token::Dimension(value, unit) => {
+                out.push_str(&shorten_number(&value.representation));
+                write_unit(out, unit);
+                return;
+            }
+            Token::Hash(value) | Token::IDHash(value) => {
+                if let Some(color) = shorten_color(value) {
+                    out.push('#');
+                    out.push_str(&color);
+                    return;
+                }
+            }
+            _ => (),
+        }
+    }
+    token.write_css(out);
+}
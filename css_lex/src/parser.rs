@@ -0,0 +1,484 @@
+use crate::lexer::*;
+use std::iter::Peekable;
+
+// From http://dev.w3.org/csswg/css-syntax/#parsing
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BlockKind {
+    // {}
+    Curly,
+    // []
+    Square,
+    // ()
+    Paren,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct SimpleBlock {
+    pub kind: BlockKind,
+    pub content: Vec<ComponentValue>,
+    // Location of the opening bracket
+    pub location: SourceLocation,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    pub arguments: Vec<ComponentValue>,
+    pub location: SourceLocation,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ComponentValue {
+    // Any token other than a function or an opening bracket
+    Preserved(Node),
+    Function(Function),
+    Block(SimpleBlock),
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct AtRule {
+    pub name: String,
+    pub prelude: Vec<ComponentValue>,
+    // Content of the {} block, if the rule has one
+    pub block: Option<Vec<ComponentValue>>,
+    // Location of the at-keyword
+    pub location: SourceLocation,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct QualifiedRule {
+    pub prelude: Vec<ComponentValue>,
+    pub block: Vec<ComponentValue>,
+    // Location of the first token of the prelude
+    pub location: SourceLocation,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Rule {
+    At(AtRule),
+    Qualified(QualifiedRule),
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Declaration {
+    pub name: String,
+    pub value: Vec<ComponentValue>,
+    pub important: bool,
+    // Location of the name
+    pub location: SourceLocation,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum DeclarationOrAtRule {
+    Declaration(Declaration),
+    AtRule(AtRule),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ParseErrorKind {
+    // There was nothing to parse but whitespace
+    Empty,
+    // Something was parsed, but was followed by more than whitespace
+    ExtraInput,
+    Invalid,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub location: SourceLocation,
+}
+
+impl ComponentValue {
+    pub fn location(&self) -> &SourceLocation {
+        match self {
+            ComponentValue::Preserved((_, location)) => location,
+            ComponentValue::Function(function) => &function.location,
+            ComponentValue::Block(block) => &block.location,
+        }
+    }
+}
+
+/**
+ * Parses a stream of tokens, as produced by a `Tokenizer`.
+ *
+ * The `consume_*` methods follow the algorithms of the same name in the
+ * specification, and the `parse_*` functions below are the entry points built
+ * on them.
+ */
+pub struct Parser<I: Iterator<Item = Node>> {
+    tokens: Peekable<I>,
+    // Location of the last token consumed, which is where errors at the end of
+    // the input are reported
+    location: SourceLocation,
+}
+
+impl<I: Iterator<Item = Node>> Parser<I> {
+    pub fn new(tokens: I) -> Parser<I> {
+        Parser {
+            tokens: tokens.peekable(),
+            location: SourceLocation {
+                line: 1,
+                column: 1,
+                span: Span { start: 0, end: 0 },
+            },
+        }
+    }
+
+    // Comments from a lossless tokenizer are skipped, as the parser never sees
+    // them.
+    #[inline]
+    fn skip_comments(&mut self) {
+        while let Some((Token::Comment(_), _)) = self.tokens.peek() {
+            self.tokens.next();
+        }
+    }
+
+    #[inline]
+    fn next(&mut self) -> Option<Node> {
+        self.skip_comments();
+        let node = self.tokens.next();
+        if let Some((_, location)) = &node {
+            self.location = location.clone();
+        }
+        node
+    }
+
+    #[inline]
+    fn peek(&mut self) -> Option<&Token> {
+        self.skip_comments();
+        self.tokens.peek().map(|(token, _)| token)
+    }
+
+    fn error(&mut self, kind: ParseErrorKind) -> ParseError {
+        self.skip_comments();
+        let location = match self.tokens.peek() {
+            Some((_, location)) => location.clone(),
+            None => self.location.clone(),
+        };
+        ParseError { kind, location }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(Token::WhiteSpace(_)) = self.peek() {
+            self.next();
+        }
+    }
+
+    #[inline]
+    fn is_eof(&mut self) -> bool {
+        self.peek().is_none()
+    }
+
+    /**
+     * Consume a list of rules. At the top level of a stylesheet `<!--` and
+     * `-->` are ignored.
+     */
+    pub fn consume_list_of_rules(&mut self, top_level: bool) -> Vec<Result<Rule, ParseError>> {
+        let mut rules = Vec::new();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::WhiteSpace(_) => {
+                    self.next();
+                }
+                Token::CDO | Token::CDC if top_level => {
+                    self.next();
+                }
+                Token::AtKeyword(_) => rules.push(Ok(Rule::At(self.consume_at_rule().unwrap()))),
+                _ => rules.push(self.consume_qualified_rule().map(Rule::Qualified)),
+            }
+        }
+        rules
+    }
+
+    /**
+     * Consume an at-rule, or return `None` without consuming anything if the
+     * next token is not an at-keyword.
+     */
+    pub fn consume_at_rule(&mut self) -> Option<AtRule> {
+        let name = match self.peek() {
+            Some(Token::AtKeyword(name)) => name.clone(),
+            _ => return None,
+        };
+        let (_, location) = self.next().unwrap();
+        let mut rule = AtRule {
+            name,
+            prelude: Vec::new(),
+            block: None,
+            location,
+        };
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Semicolon => {
+                    self.next();
+                    break;
+                }
+                Token::LeftCurlyBracket => {
+                    let (_, location) = self.next().unwrap();
+                    rule.block = Some(
+                        self.consume_simple_block(BlockKind::Curly, location)
+                            .content,
+                    );
+                    break;
+                }
+                _ => {
+                    let value = self.consume_component_value().unwrap();
+                    rule.prelude.push(value);
+                }
+            }
+        }
+        Some(rule)
+    }
+
+    /**
+     * Consume a qualified rule. Returns an error if the input ends before the
+     * rule's block.
+     */
+    pub fn consume_qualified_rule(&mut self) -> Result<QualifiedRule, ParseError> {
+        self.skip_comments();
+        let location = match self.tokens.peek() {
+            Some((_, location)) => location.clone(),
+            None => return Err(self.error(ParseErrorKind::Invalid)),
+        };
+        let mut prelude = Vec::new();
+        while let Some(token) = self.peek() {
+            if *token == Token::LeftCurlyBracket {
+                let (_, block_location) = self.next().unwrap();
+                let block = self.consume_simple_block(BlockKind::Curly, block_location);
+                return Ok(QualifiedRule {
+                    prelude,
+                    block: block.content,
+                    location,
+                });
+            }
+            prelude.push(self.consume_component_value().unwrap());
+        }
+        Err(self.error(ParseErrorKind::Invalid))
+    }
+
+    /**
+     * Consume a list of declarations, such as the content of a style rule's
+     * block. Anything that is neither a declaration nor an at-rule is skipped
+     * up to the next `;` and reported as an error.
+     */
+    pub fn consume_list_of_declarations(&mut self) -> Vec<Result<DeclarationOrAtRule, ParseError>> {
+        let mut declarations = Vec::new();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::WhiteSpace(_) | Token::Semicolon => {
+                    self.next();
+                }
+                Token::AtKeyword(_) => {
+                    declarations.push(Ok(DeclarationOrAtRule::AtRule(
+                        self.consume_at_rule().unwrap(),
+                    )));
+                }
+                Token::Ident(name) => {
+                    let name = name.clone();
+                    let (_, location) = self.next().unwrap();
+                    let mut values = Vec::new();
+                    while !matches!(self.peek(), None | Some(Token::Semicolon)) {
+                        values.push(self.consume_component_value().unwrap());
+                    }
+                    declarations.push(
+                        consume_declaration(name, location, values)
+                            .map(DeclarationOrAtRule::Declaration),
+                    );
+                }
+                _ => {
+                    let error = self.error(ParseErrorKind::Invalid);
+                    while !matches!(self.peek(), None | Some(Token::Semicolon)) {
+                        self.consume_component_value();
+                    }
+                    declarations.push(Err(error));
+                }
+            }
+        }
+        declarations
+    }
+
+    /**
+     * Consume a component value, or return `None` at the end of the input.
+     */
+    pub fn consume_component_value(&mut self) -> Option<ComponentValue> {
+        let (token, location) = self.next()?;
+        Some(match token {
+            Token::LeftCurlyBracket => {
+                ComponentValue::Block(self.consume_simple_block(BlockKind::Curly, location))
+            }
+            Token::LeftBracket => {
+                ComponentValue::Block(self.consume_simple_block(BlockKind::Square, location))
+            }
+            Token::LeftParen => {
+                ComponentValue::Block(self.consume_simple_block(BlockKind::Paren, location))
+            }
+            Token::Function(name) => {
+                let mut arguments = Vec::new();
+                while !matches!(self.peek(), None | Some(Token::RightParen)) {
+                    arguments.push(self.consume_component_value().unwrap());
+                }
+                self.next();
+                ComponentValue::Function(Function {
+                    name,
+                    arguments,
+                    location,
+                })
+            }
+            token => ComponentValue::Preserved((token, location)),
+        })
+    }
+
+    /**
+     * Consume the content of a block whose opening bracket was just consumed,
+     * up to and including the matching closing bracket.
+     */
+    fn consume_simple_block(&mut self, kind: BlockKind, location: SourceLocation) -> SimpleBlock {
+        let ending = match kind {
+            BlockKind::Curly => Token::RightCurlyBracket,
+            BlockKind::Square => Token::RightBracket,
+            BlockKind::Paren => Token::RightParen,
+        };
+        let mut content = Vec::new();
+        while let Some(token) = self.peek() {
+            if *token == ending {
+                self.next();
+                break;
+            }
+            content.push(self.consume_component_value().unwrap());
+        }
+        SimpleBlock {
+            kind,
+            content,
+            location,
+        }
+    }
+}
+
+/**
+ * Consume a declaration from the name, at `location`, and the `values` which
+ * follow it.
+ */
+fn consume_declaration(
+    name: String,
+    location: SourceLocation,
+    values: Vec<ComponentValue>,
+) -> Result<Declaration, ParseError> {
+    let is_whitespace =
+        |v: &ComponentValue| matches!(v, ComponentValue::Preserved((Token::WhiteSpace(_), _)));
+    let mut values = values.into_iter().skip_while(is_whitespace);
+    match values.next() {
+        Some(ComponentValue::Preserved((Token::Colon, _))) => (),
+        other => {
+            let location = other.map_or(location, |v| v.location().clone());
+            return Err(ParseError {
+                kind: ParseErrorKind::Invalid,
+                location,
+            });
+        }
+    }
+
+    let mut value: Vec<ComponentValue> = values.skip_while(is_whitespace).collect();
+    let significant: Vec<usize> = (0..value.len())
+        .filter(|&i| !is_whitespace(&value[i]))
+        .collect();
+    let mut important = false;
+    if let [.., bang, last] = significant[..] {
+        if let (
+            ComponentValue::Preserved((Token::Delim('!'), _)),
+            ComponentValue::Preserved((Token::Ident(ident), _)),
+        ) = (&value[bang], &value[last])
+        {
+            if ident.eq_ignore_ascii_case("important") {
+                value.truncate(bang);
+                important = true;
+            }
+        }
+    }
+    // Whitespace around the value, including before `!important`, is not
+    // part of it
+    while value.last().is_some_and(is_whitespace) {
+        value.pop();
+    }
+    Ok(Declaration {
+        name,
+        value,
+        important,
+        location,
+    })
+}
+
+pub fn parse_stylesheet(input: &str) -> Vec<Result<Rule, ParseError>> {
+    Parser::new(tokenize(input)).consume_list_of_rules(true)
+}
+
+pub fn parse_rule_list(input: &str) -> Vec<Result<Rule, ParseError>> {
+    Parser::new(tokenize(input)).consume_list_of_rules(false)
+}
+
+/**
+ * Parse a single rule, surrounded by nothing but whitespace.
+ */
+pub fn parse_rule(input: &str) -> Result<Rule, ParseError> {
+    let mut parser = Parser::new(tokenize(input));
+    parser.skip_whitespace();
+    let rule = match parser.peek() {
+        None => return Err(parser.error(ParseErrorKind::Empty)),
+        Some(Token::AtKeyword(_)) => Rule::At(parser.consume_at_rule().unwrap()),
+        Some(_) => Rule::Qualified(parser.consume_qualified_rule()?),
+    };
+    parser.skip_whitespace();
+    if !parser.is_eof() {
+        return Err(parser.error(ParseErrorKind::ExtraInput));
+    }
+    Ok(rule)
+}
+
+pub fn parse_declaration_list(input: &str) -> Vec<Result<DeclarationOrAtRule, ParseError>> {
+    Parser::new(tokenize(input)).consume_list_of_declarations()
+}
+
+/**
+ * Parse a single declaration, such as the condition of an `@supports` rule.
+ */
+pub fn parse_declaration(input: &str) -> Result<Declaration, ParseError> {
+    let mut parser = Parser::new(tokenize(input));
+    parser.skip_whitespace();
+    let name = match parser.peek() {
+        Some(Token::Ident(name)) => name.clone(),
+        None => return Err(parser.error(ParseErrorKind::Empty)),
+        Some(_) => return Err(parser.error(ParseErrorKind::Invalid)),
+    };
+    let (_, location) = parser.next().unwrap();
+    let mut values = Vec::new();
+    while let Some(value) = parser.consume_component_value() {
+        values.push(value);
+    }
+    consume_declaration(name, location, values)
+}
+
+/**
+ * Parse a single component value, surrounded by nothing but whitespace.
+ */
+pub fn parse_component_value(input: &str) -> Result<ComponentValue, ParseError> {
+    let mut parser = Parser::new(tokenize(input));
+    parser.skip_whitespace();
+    let value = match parser.consume_component_value() {
+        Some(value) => value,
+        None => return Err(parser.error(ParseErrorKind::Empty)),
+    };
+    parser.skip_whitespace();
+    if !parser.is_eof() {
+        return Err(parser.error(ParseErrorKind::ExtraInput));
+    }
+    Ok(value)
+}
+
+pub fn parse_component_value_list(input: &str) -> Vec<ComponentValue> {
+    let mut parser = Parser::new(tokenize(input));
+    let mut values = Vec::new();
+    while let Some(value) = parser.consume_component_value() {
+        values.push(value);
+    }
+    values
+}
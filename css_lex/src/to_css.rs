@@ -0,0 +1,255 @@
+use crate::lexer::*;
+use std::fmt;
+use std::ops::Deref;
+
+// From http://dev.w3.org/csswg/css-syntax/#serialization
+
+impl<T: Deref<Target = str>> Token<T> {
+    /**
+     * Serialize the token so that it is read back as the same token.
+     *
+     * A bad string or a lone `\` is followed by a newline, as the tokenizer
+     * only produces them before one.
+     */
+    pub fn to_css(&self) -> String {
+        let mut out = String::new();
+        self.write_css(&mut out);
+        out
+    }
+
+    pub fn write_css(&self, out: &mut String) {
+        match self {
+            Token::Ident(value) => write_name(out, value, true),
+            Token::Function(name) => {
+                write_name(out, name, true);
+                out.push('(');
+            }
+            Token::AtKeyword(value) => {
+                out.push('@');
+                write_name(out, value, true);
+            }
+            Token::Hash(value) => {
+                out.push('#');
+                write_name(out, value, false);
+            }
+            Token::IDHash(value) => {
+                out.push('#');
+                write_name(out, value, true);
+            }
+            Token::String(value) => write_string(out, value),
+            Token::BadString => out.push_str("\"\n"),
+            Token::URL(value) => write_url(out, value),
+            Token::BadURL => out.push_str("url(()"),
+            Token::Delim('\\') => out.push_str("\\\n"),
+            Token::Delim(c) => out.push(*c),
+            Token::Number(value) => out.push_str(&value.representation),
+            Token::Percentage(value) => {
+                out.push_str(&value.representation);
+                out.push('%');
+            }
+            Token::Dimension(value, unit) => {
+                out.push_str(&value.representation);
+                write_unit(out, unit);
+            }
+            Token::UnicodeRange(start, end) if start == end => {
+                out.push_str(&format!("U+{:X}", start))
+            }
+            Token::UnicodeRange(start, end) => out.push_str(&format!("U+{:X}-{:X}", start, end)),
+            Token::IncludeMatch => out.push_str("~="),
+            Token::DashMatch => out.push_str("|="),
+            Token::PrefixMatch => out.push_str("^="),
+            Token::SuffixMatch => out.push_str("$="),
+            Token::SubstringMatch => out.push_str("*="),
+            Token::Column => out.push_str("||"),
+            Token::WhiteSpace(Some(text)) | Token::Comment(text) => out.push_str(text),
+            Token::WhiteSpace(None) => out.push(' '),
+            Token::CDO => out.push_str("<!--"),
+            Token::CDC => out.push_str("-->"),
+            Token::Colon => out.push(':'),
+            Token::Semicolon => out.push(';'),
+            Token::Comma => out.push(','),
+            Token::LeftBracket => out.push('['),
+            Token::RightBracket => out.push(']'),
+            Token::LeftParen => out.push('('),
+            Token::RightParen => out.push(')'),
+            Token::LeftCurlyBracket => out.push('{'),
+            Token::RightCurlyBracket => out.push('}'),
+        }
+    }
+}
+
+/**
+ * Formats the token as CSS, like `to_css`.
+ */
+impl<T: Deref<Target = str>> fmt::Display for Token<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_css())
+    }
+}
+
+/**
+ * Serialize a list of tokens, inserting an empty comment between two tokens
+ * which would otherwise be read back as different tokens.
+ *
+ * Tokenizing the result gives the same tokens again, although whitespace
+ * tokens may come out longer.
+ */
+pub fn serialize_tokens(tokens: &[Node]) -> String {
+    let mut out = String::new();
+    let mut previous: Option<&Token> = None;
+    for (token, _) in tokens {
+        if previous.is_some_and(|previous| would_merge(previous, token)) {
+            out.push_str("/**/");
+        }
+        token.write_css(&mut out);
+        previous = Some(token);
+    }
+    out
+}
+
+/**
+ * Whether `next` written right after `previous` would be read back as
+ * different tokens.
+ *
+ * From the table in the specification, plus whitespace, and unicode range and
+ * match tokens, which the legacy tokenizer reads greedily.
+ */
+pub fn would_merge(previous: &Token, next: &Token) -> bool {
+    let ident_like = matches!(
+        next,
+        Token::Ident(_) | Token::Function(_) | Token::URL(_) | Token::BadURL
+    );
+    let numeric = matches!(
+        next,
+        Token::Number(_) | Token::Percentage(_) | Token::Dimension(..)
+    );
+    match previous {
+        // `u+` followed by a hex digit or `?` starts a unicode range.
+        Token::Ident(name) if name.eq_ignore_ascii_case("u") && *next == Token::Delim('+') => true,
+        // `--` followed by `>` is a CDC token.
+        Token::Ident(name) if name == "--" && *next == Token::Delim('>') => true,
+        Token::Ident(_) => {
+            ident_like
+                || numeric
+                || matches!(next, Token::Delim('-') | Token::CDC | Token::LeftParen)
+        }
+        Token::AtKeyword(_) | Token::Hash(_) | Token::IDHash(_) | Token::Dimension(..) => {
+            ident_like || numeric || matches!(next, Token::Delim('-') | Token::CDC)
+        }
+        Token::Delim('#') => {
+            ident_like || numeric || matches!(next, Token::Delim('-') | Token::CDC)
+        }
+        Token::Delim('-') => {
+            ident_like || numeric || matches!(next, Token::Delim('-') | Token::CDC)
+        }
+        Token::Number(_) => ident_like || numeric || matches!(next, Token::Delim('%') | Token::CDC),
+        Token::Delim('@') => ident_like || matches!(next, Token::Delim('-') | Token::CDC),
+        Token::Delim('.') | Token::Delim('+') => numeric,
+        // `<!` followed by `--` is a CDO token.
+        Token::Delim('<') => matches!(next, Token::Delim('!')),
+        Token::Delim('!') => match next {
+            Token::Ident(name) | Token::Function(name) => name.starts_with("--"),
+            _ => *next == Token::CDC,
+        },
+        Token::Delim('/') => matches!(next, Token::Delim('*')),
+        Token::Delim('$' | '*' | '^' | '~') => matches!(next, Token::Delim('=')),
+        Token::Delim('|') => matches!(next, Token::Delim('=' | '|')),
+        Token::UnicodeRange(..) => ident_like || numeric || matches!(next, Token::Delim('?' | '-')),
+        // Two whitespace tokens can only come from whitespace on both sides
+        // of a comment.
+        Token::WhiteSpace(_) => matches!(next, Token::WhiteSpace(_)),
+        _ => false,
+    }
+}
+
+/**
+ * Write the unit of a dimension. An `e` followed by a digit would be read as
+ * the number's exponent, so it is escaped.
+ */
+pub(crate) fn write_unit(out: &mut String, unit: &str) {
+    let mut rest = unit.chars().skip(1).skip_while(|&c| c == '+' || c == '-');
+    if unit.starts_with(['e', 'E']) && rest.next().is_some_and(|c| c.is_ascii_digit()) {
+        write_hex_escape(out, unit.chars().next().unwrap());
+        write_name(out, &unit[1..], false);
+    } else {
+        write_name(out, unit, true);
+    }
+}
+
+fn write_hex_escape(out: &mut String, c: char) {
+    out.push_str(&format!("\\{:x} ", c as u32));
+}
+
+#[inline]
+fn is_name_start(c: char) -> bool {
+    matches!(c, 'a'..='z' | 'A'..='Z' | '_') || c > '\x7F'
+}
+
+#[inline]
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || matches!(c, '0'..='9' | '-')
+}
+
+/**
+ * Write a name, escaping what would not be read back as part of it. If
+ * `ident` is set, the start is also escaped where needed for it to be read as
+ * an identifier rather than a number or a delimiter.
+ */
+fn write_name(out: &mut String, value: &str, ident: bool) {
+    if ident && value == "-" {
+        out.push_str("\\-");
+        return;
+    }
+    // An identifier may start with `--`, which needs no escape.
+    let check_start = ident && !value.starts_with("--");
+    let first = usize::from(value.starts_with('-'));
+    for (idx, c) in value.chars().enumerate() {
+        if check_start && idx == first && !is_name_start(c) {
+            match c {
+                '0'..='9' => write_hex_escape(out, c),
+                c if c < ' ' || c == '\x7F' => write_hex_escape(out, c),
+                c => {
+                    out.push('\\');
+                    out.push(c);
+                }
+            }
+        } else if is_name_char(c) {
+            out.push(c);
+        } else if c < ' ' || c == '\x7F' {
+            write_hex_escape(out, c);
+        } else {
+            out.push('\\');
+            out.push(c);
+        }
+    }
+}
+
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c < ' ' || c == '\x7F' => write_hex_escape(out, c),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_url(out: &mut String, value: &str) {
+    out.push_str("url(");
+    for c in value.chars() {
+        match c {
+            '"' | '\'' | '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c <= ' ' || c == '\x7F' => write_hex_escape(out, c),
+            c => out.push(c),
+        }
+    }
+    out.push(')');
+}
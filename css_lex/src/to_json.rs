@@ -1,4 +1,5 @@
 use crate::lexer::*;
+use crate::parser::*;
 use serde_json::{json, Value};
 
 pub fn json_almost_equals(a: &Value, b: &Value) -> bool {
@@ -14,7 +15,11 @@ pub fn json_almost_equals(a: &Value, b: &Value) -> bool {
                     .zip(b.iter())
                     .all(|(a, b)| json_almost_equals(a, b))
         }
-        (Value::Object(_), Value::Object(_)) => todo!(),
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| json_almost_equals(a, b)))
+        }
         (Value::Null, Value::Null) => true,
         _ => false,
     }
@@ -72,7 +77,8 @@ impl Token {
             Token::SuffixMatch => json!("$="),
             Token::SubstringMatch => json!("*="),
             Token::Column => json!("||"),
-            Token::WhiteSpace => json!(" "),
+            Token::WhiteSpace(_) => json!(" "),
+            Token::Comment(text) => json!(["comment", text]),
             Token::CDO => json!("<!--"),
             Token::CDC => json!("-->"),
             Token::Colon => json!(":"),
@@ -105,3 +111,96 @@ impl NumericValue {
 pub fn list_to_json(list: &[(Token, SourceLocation)]) -> Vec<Value> {
     list.iter().map(|(c, _)| c.to_json()).collect()
 }
+
+impl ComponentValue {
+    pub fn to_json(&self) -> Value {
+        match self {
+            ComponentValue::Preserved((token, _)) => token.to_json(),
+            ComponentValue::Function(function) => {
+                let mut res = vec![json!("function"), json!(function.name)];
+                res.extend(function.arguments.iter().map(ComponentValue::to_json));
+                Value::Array(res)
+            }
+            ComponentValue::Block(block) => {
+                let name = match block.kind {
+                    BlockKind::Curly => "{}",
+                    BlockKind::Square => "[]",
+                    BlockKind::Paren => "()",
+                };
+                let mut res = vec![json!(name)];
+                res.extend(block.content.iter().map(ComponentValue::to_json));
+                Value::Array(res)
+            }
+        }
+    }
+}
+
+pub fn component_values_to_json(values: &[ComponentValue]) -> Value {
+    Value::Array(values.iter().map(ComponentValue::to_json).collect())
+}
+
+impl AtRule {
+    pub fn to_json(&self) -> Value {
+        json!([
+            "at-rule",
+            self.name,
+            component_values_to_json(&self.prelude),
+            match &self.block {
+                Some(block) => component_values_to_json(block),
+                None => Value::Null,
+            }
+        ])
+    }
+}
+
+impl QualifiedRule {
+    pub fn to_json(&self) -> Value {
+        json!([
+            "qualified rule",
+            component_values_to_json(&self.prelude),
+            component_values_to_json(&self.block)
+        ])
+    }
+}
+
+impl Rule {
+    pub fn to_json(&self) -> Value {
+        match self {
+            Rule::At(rule) => rule.to_json(),
+            Rule::Qualified(rule) => rule.to_json(),
+        }
+    }
+}
+
+impl Declaration {
+    pub fn to_json(&self) -> Value {
+        json!([
+            "declaration",
+            self.name,
+            component_values_to_json(&self.value),
+            self.important
+        ])
+    }
+}
+
+impl DeclarationOrAtRule {
+    pub fn to_json(&self) -> Value {
+        match self {
+            DeclarationOrAtRule::Declaration(declaration) => declaration.to_json(),
+            DeclarationOrAtRule::AtRule(rule) => rule.to_json(),
+        }
+    }
+}
+
+impl ParseError {
+    pub fn to_json(&self) -> Value {
+        json!([
+            "error",
+            match self.kind {
+                ParseErrorKind::Empty => "empty",
+                ParseErrorKind::ExtraInput => "extra-input",
+                ParseErrorKind::Invalid => "invalid",
+            }
+        ])
+    }
+}
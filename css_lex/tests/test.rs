@@ -1,7 +1,16 @@
+use css_lex::diagnostic::*;
+use css_lex::html::*;
+use css_lex::incremental::*;
 use css_lex::lexer::*;
+use css_lex::line_index::*;
+use css_lex::minify::*;
+use css_lex::parser::*;
+use css_lex::to_css::*;
 use css_lex::to_json::*;
+use proptest::prelude::*;
 use serde::Serialize;
 use serde_json::Value;
+use std::borrow::Cow;
 use std::fmt::Debug;
 
 fn run_json_tests<T, F>(json_data: &str, parse: F)
@@ -34,12 +43,16 @@ where
 #[test]
 fn tokenize_simple() {
     let mut t = tokenize("a");
-    assert!(
-        t.next()
-            == Some((
-                Token::Ident("a".to_string()),
-                SourceLocation { line: 1, column: 1 }
-            ))
+    assert_eq!(
+        t.next(),
+        Some((
+            Token::Ident("a".to_string()),
+            SourceLocation {
+                line: 1,
+                column: 1,
+                span: Span { start: 0, end: 1 },
+            }
+        ))
     );
 }
 
@@ -54,3 +67,772 @@ fn test_tokenize_json() {
         list_to_json(&token_list)
     });
 }
+
+#[test]
+fn test_tokenize_legacy_json() {
+    let options = TokenizerOptions {
+        legacy: true,
+        ..Default::default()
+    };
+    run_json_tests(include_str!("tokens_legacy.json"), |input| {
+        let token_list: Vec<Node> = tokenize_with_options(&input, options).collect();
+        list_to_json(&token_list)
+    });
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let items: Vec<Value> = serde_json::from_str(include_str!("tokens.json")).unwrap();
+    for input in items.iter().step_by(2).map(|item| item.as_str().unwrap()) {
+        let legacy = TokenizerOptions {
+            lossless: true,
+            legacy: true,
+            ..Default::default()
+        };
+        for options in [TokenizerOptions::default(), legacy] {
+            let nodes: Vec<Node> = tokenize_with_options(input, options).collect();
+            let json = serde_json::to_string(&nodes).unwrap();
+            let deserialized: Vec<Node> = serde_json::from_str(&json).unwrap();
+            assert_eq!(deserialized, nodes, "{:?}", input);
+        }
+    }
+
+    let nodes: Vec<Node> = tokenize("a:1.5px").collect();
+    assert_eq!(
+        serde_json::to_value(&nodes).unwrap(),
+        serde_json::json!([
+            [
+                {"type": "ident", "value": "a"},
+                {"line": 1, "column": 1, "span": {"start": 0, "end": 1}}
+            ],
+            [
+                {"type": "colon"},
+                {"line": 1, "column": 2, "span": {"start": 1, "end": 2}}
+            ],
+            [
+                {
+                    "type": "dimension",
+                    "value": [{"representation": "1.5", "value": 1.5, "int_value": null}, "px"]
+                },
+                {"line": 1, "column": 3, "span": {"start": 2, "end": 7}}
+            ]
+        ])
+    );
+}
+
+#[test]
+fn test_json_almost_equals() {
+    let a: Value = serde_json::from_str(r#"{"a": [1, {"b": 0.5}], "c": null}"#).unwrap();
+    let b: Value = serde_json::from_str(r#"{"c": null, "a": [1.0000001, {"b": 0.5}]}"#).unwrap();
+    let c: Value = serde_json::from_str(r#"{"a": [1, {"b": 0.5}], "d": null}"#).unwrap();
+    assert!(json_almost_equals(&a, &b));
+    assert!(!json_almost_equals(&a, &c));
+}
+
+#[test]
+fn test_spans() {
+    let items: Vec<Value> = serde_json::from_str(include_str!("tokens.json")).unwrap();
+    for input in items.iter().step_by(2).map(|item| item.as_str().unwrap()) {
+        let input = preprocess(input);
+        let starts = line_starts(&input);
+        let mut end = 0;
+        for (_, location) in tokenize(&input) {
+            let span = location.span;
+            assert_eq!(span.start, starts[location.line - 1] + location.column - 1);
+            // Only comments come between tokens
+            let gap = &input[end..span.start];
+            assert!(gap.is_empty() || gap.starts_with("/*"), "{:?}", input);
+            assert!(!source_text(&input, span).is_empty());
+            end = span.end;
+        }
+        assert!(end == input.len() || input[end..].starts_with("/*"));
+    }
+}
+
+#[test]
+fn test_original_locations() {
+    let input = "a\r\n\x00b\x0C c /* \r\n */\r\n  \"\x00\" d";
+    let nodes: Vec<Node> = tokenize(input).collect();
+    let texts: Vec<&str> = nodes
+        .iter()
+        .map(|(_, location)| source_text(input, location.span))
+        .collect();
+    assert_eq!(
+        texts,
+        ["a", "\r\n", "\x00b", "\x0C ", "c", " ", "\r\n  ", "\"\x00\"", " ", "d"]
+    );
+    let positions: Vec<(usize, usize)> = nodes
+        .iter()
+        .map(|(_, location)| (location.line, location.column))
+        .collect();
+    assert_eq!(
+        positions,
+        [
+            (1, 1),
+            (1, 2),
+            (2, 1),
+            (2, 3),
+            (3, 2),
+            (3, 3),
+            (4, 4),
+            (5, 3),
+            (5, 6),
+            (5, 7)
+        ]
+    );
+}
+
+#[test]
+fn test_preprocess_into() {
+    for input in ["", "abc", "a\r\n\x00b\x0C c\r", "\r\r\n\n\x00é"] {
+        let (expected, expected_map) = preprocess_with_map(input);
+        assert_eq!(preprocessed_len(input), expected.len());
+        let mut out = vec![0; expected.len()];
+        let map = preprocess_into(input, &mut out);
+        assert_eq!(out, expected.as_bytes());
+        for offset in 0..=expected.len() {
+            assert_eq!(
+                map.original_offset(offset),
+                expected_map.original_offset(offset)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_column_units() {
+    let items: Vec<Value> = serde_json::from_str(include_str!("tokens.json")).unwrap();
+    let inputs = items.iter().step_by(2).map(|item| item.as_str().unwrap());
+    // Form feeds end lines for the tokenizer but not for the index
+    let inputs = inputs.filter(|input| !input.contains('\x0C'));
+    for input in inputs.chain(["é 😀 a\r\nb\x00 \u{10000}c /* ü */ d\re"]) {
+        let index = LineIndex::new(input);
+        for unit in [ColumnUnit::Utf8, ColumnUnit::Utf16, ColumnUnit::Utf32] {
+            let options = TokenizerOptions {
+                column_unit: unit,
+                ..Default::default()
+            };
+            for (_, location) in tokenize_with_options(input, options) {
+                let start = location.span.start;
+                assert_eq!(
+                    index.line_column(start, unit),
+                    (location.line, location.column),
+                    "{:?}",
+                    input
+                );
+                assert_eq!(
+                    index.offset(location.line, location.column, unit),
+                    Some(start)
+                );
+            }
+        }
+    }
+
+    let columns = |unit| -> Vec<usize> {
+        let options = TokenizerOptions {
+            column_unit: unit,
+            ..Default::default()
+        };
+        tokenize_with_options("é 😀 a", options)
+            .map(|(_, location)| location.column)
+            .collect()
+    };
+    assert_eq!(columns(ColumnUnit::Utf8), [1, 3, 4, 8, 9]);
+    assert_eq!(columns(ColumnUnit::Utf16), [1, 2, 3, 5, 6]);
+    assert_eq!(columns(ColumnUnit::Utf32), [1, 2, 3, 4, 5]);
+
+    let index = LineIndex::new("😀a\r\nb");
+    assert_eq!(index.line_count(), 2);
+    assert_eq!(index.offset(1, 2, ColumnUnit::Utf16), None);
+    assert_eq!(index.offset(1, 3, ColumnUnit::Utf16), Some(4));
+    assert_eq!(index.offset(1, 4, ColumnUnit::Utf16), Some(5));
+    assert_eq!(index.offset(1, 5, ColumnUnit::Utf16), None);
+    assert_eq!(index.offset(2, 1, ColumnUnit::Utf32), Some(7));
+    assert_eq!(index.offset(3, 1, ColumnUnit::Utf32), None);
+    assert_eq!(index.line_column(8, ColumnUnit::Utf16), (2, 2));
+
+    let index = LineIndex::new("a\x0Cb\rc");
+    assert_eq!(index.line_count(), 2);
+    assert_eq!(index.line_column(2, ColumnUnit::Utf8), (1, 3));
+    assert_eq!(index.offset(1, 4, ColumnUnit::Utf8), Some(3));
+    let (_, location) = tokenize("a\x0Cb").nth(2).unwrap();
+    assert_eq!((location.line, location.column), (2, 1));
+}
+
+#[test]
+fn test_tokenize_borrowed() {
+    let items: Vec<Value> = serde_json::from_str(include_str!("tokens.json")).unwrap();
+    for input in items.iter().step_by(2).map(|item| item.as_str().unwrap()) {
+        let input = preprocess(input);
+        let borrowed: Vec<Node> = tokenize_borrowed(&input)
+            .map(|(token, location)| (token.into_owned(), location))
+            .collect();
+        assert_eq!(borrowed, tokenize(&input).collect::<Vec<Node>>());
+    }
+
+    let input = "a\\62  c url( d.png ) 'e\\\nf' 1.5em";
+    let tokens: Vec<_> = tokenize_borrowed(input).map(|(token, _)| token).collect();
+    let borrowed = |value: &Cow<str>| matches!(value, Cow::Borrowed(_));
+    match &tokens[..] {
+        [Token::Ident(ab), _, Token::Ident(c), _, Token::URL(url), _, Token::String(ef), _, Token::Dimension(number, unit)] =>
+        {
+            assert!(ab == "ab" && !borrowed(ab));
+            assert!(c == "c" && borrowed(c));
+            assert!(url == "d.png" && borrowed(url));
+            assert!(ef == "ef" && !borrowed(ef));
+            assert!(borrowed(&number.representation) && borrowed(unit));
+        }
+        _ => panic!("unexpected tokens"),
+    }
+}
+
+#[test]
+fn test_lossless() {
+    let options = TokenizerOptions {
+        lossless: true,
+        ..Default::default()
+    };
+    let items: Vec<Value> = serde_json::from_str(include_str!("tokens.json")).unwrap();
+    for input in items.iter().step_by(2).map(|item| item.as_str().unwrap()) {
+        let input = preprocess(input);
+        let lossless: Vec<Node> = tokenize_with_options(&input, options).collect();
+        let text: String = lossless
+            .iter()
+            .map(|(_, location)| source_text(&input, location.span))
+            .collect();
+        assert_eq!(text, input);
+
+        let without_comments: Vec<Node> = lossless
+            .into_iter()
+            .filter(|(token, _)| !matches!(token, Token::Comment(_)))
+            .map(|(token, location)| match token {
+                Token::WhiteSpace(_) => (Token::WhiteSpace(None), location),
+                token => (token, location),
+            })
+            .collect();
+        assert_eq!(without_comments, tokenize(&input).collect::<Vec<Node>>());
+    }
+
+    let tokens: Vec<Token> = tokenize_with_options("/* a */\n\t b/**//* c", options)
+        .map(|(token, _)| token)
+        .collect();
+    assert_eq!(
+        tokens,
+        [
+            Token::Comment("/* a */".to_string()),
+            Token::WhiteSpace(Some("\n\t ".to_string())),
+            Token::Ident("b".to_string()),
+            Token::Comment("/**/".to_string()),
+            Token::Comment("/* c".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_diagnostics() {
+    let cases = [
+        ("a { b: c }", vec![]),
+        ("\"abc", vec![(DiagnosticCode::UnterminatedString, 0, 4)]),
+        (
+            "a 'b\nc'",
+            vec![
+                (DiagnosticCode::NewlineInString, 4, 5),
+                (DiagnosticCode::UnterminatedString, 6, 7),
+            ],
+        ),
+        ("a /* b", vec![(DiagnosticCode::UnterminatedComment, 2, 6)]),
+        ("url( a", vec![(DiagnosticCode::UnterminatedUrl, 0, 6)]),
+        ("url(a\"b) c", vec![(DiagnosticCode::BadUrlCharacter, 5, 6)]),
+        ("url(a\\\nb) c", vec![(DiagnosticCode::InvalidEscape, 5, 6)]),
+        ("a \\\n", vec![(DiagnosticCode::InvalidEscape, 2, 3)]),
+        ("a\\", vec![(DiagnosticCode::EscapedEof, 1, 2)]),
+        (
+            "\r\n\x00 /*",
+            vec![(DiagnosticCode::UnterminatedComment, 4, 6)],
+        ),
+    ];
+    for (input, expected) in cases {
+        let mut tokenizer = tokenize(input);
+        tokenizer.by_ref().for_each(drop);
+        let diagnostics: Vec<_> = tokenizer
+            .diagnostics
+            .iter()
+            .map(|d| {
+                assert!(d.message == d.code.message());
+                (d.code, d.span.start, d.span.end)
+            })
+            .collect();
+        assert_eq!(diagnostics, expected, "{:?}", input);
+    }
+}
+
+#[test]
+fn test_checkpoint() {
+    let input = "a {\n  b: 'c";
+    let expected: Vec<Node> = tokenize(input).collect();
+
+    let mut tokenizer = tokenize(input);
+    assert_eq!(tokenizer.next().as_ref(), Some(&expected[0]));
+    let checkpoint = tokenizer.checkpoint();
+    assert_eq!(tokenizer.peek(), Some(&expected[1]));
+    assert_eq!(tokenizer.peek_nth(6), Some(&expected[7]));
+    assert_eq!(tokenizer.peek_nth(7), None);
+    assert_eq!(tokenizer.diagnostics.len(), 1);
+    assert_eq!(tokenizer.checkpoint(), checkpoint);
+    assert_eq!(tokenizer.next().as_ref(), Some(&expected[1]));
+    assert_eq!(tokenizer.next().as_ref(), Some(&expected[2]));
+    assert_eq!(tokenizer.checkpoint().line, 1);
+    assert_eq!(tokenizer.next().as_ref(), Some(&expected[3]));
+    assert_eq!(tokenizer.checkpoint().line, 2);
+
+    tokenizer.restore(checkpoint);
+    assert!(tokenizer.diagnostics.is_empty());
+    assert_eq!(tokenizer.by_ref().collect::<Vec<Node>>(), expected[1..]);
+    assert_eq!(tokenizer.diagnostics.len(), 1);
+
+    // Lexing from the start of a token on a line of its own
+    let mut tokenizer = tokenize(input);
+    tokenizer.reset_to(TokenizerState::at_line_start(6));
+    let (token, location) = tokenizer.next().unwrap();
+    assert_eq!(token, Token::Ident("b".to_string()));
+    assert_eq!((location.line, location.column), (1, 1));
+
+    let mut borrowed = tokenize_borrowed(input);
+    borrowed.tokenizer.peek_nth(1);
+    let tokens: Vec<Node> = borrowed
+        .map(|(token, location)| (token.into_owned(), location))
+        .collect();
+    assert_eq!(tokens, expected);
+}
+
+#[test]
+fn test_huge_numbers() {
+    let options = TokenizerOptions {
+        legacy: true,
+        ..Default::default()
+    };
+    let tokens: Vec<Token> = tokenize_with_options(
+        "99999999999999999999px -99999999999999999999 +1e999 u+fffff?",
+        options,
+    )
+    .map(|(token, _)| token)
+    .collect();
+    match &tokens[..] {
+        [Token::Dimension(a, _), _, Token::Number(b), _, Token::Number(c), _, Token::UnicodeRange(start, end)] =>
+        {
+            assert!(a.int_value == Some(i64::MAX) && a.value == 1e20);
+            assert!(b.int_value == Some(i64::MIN));
+            assert!(c.int_value.is_none() && c.value == f64::MAX);
+            assert!(*start == 0xFFFFF0 && *end == 0xFFFFFF);
+        }
+        _ => panic!("unexpected tokens"),
+    }
+}
+
+fn parse_results_to_json<T>(results: &[Result<T, ParseError>], to_json: fn(&T) -> Value) -> Value {
+    Value::Array(
+        results
+            .iter()
+            .map(|result| match result {
+                Ok(value) => to_json(value),
+                Err(error) => error.to_json(),
+            })
+            .collect(),
+    )
+}
+
+fn parse_result_to_json<T>(result: Result<T, ParseError>, to_json: fn(&T) -> Value) -> Value {
+    match result {
+        Ok(value) => to_json(&value),
+        Err(error) => error.to_json(),
+    }
+}
+
+#[test]
+fn test_component_value_list_json() {
+    run_json_tests(include_str!("component_value_list.json"), |input| {
+        component_values_to_json(&parse_component_value_list(&input))
+    });
+}
+
+#[test]
+fn test_one_component_value_json() {
+    run_json_tests(include_str!("one_component_value.json"), |input| {
+        parse_result_to_json(parse_component_value(&input), ComponentValue::to_json)
+    });
+}
+
+#[test]
+fn test_declaration_list_json() {
+    run_json_tests(include_str!("declaration_list.json"), |input| {
+        parse_results_to_json(
+            &parse_declaration_list(&input),
+            DeclarationOrAtRule::to_json,
+        )
+    });
+}
+
+#[test]
+fn test_one_declaration_json() {
+    run_json_tests(include_str!("one_declaration.json"), |input| {
+        parse_result_to_json(parse_declaration(&input), Declaration::to_json)
+    });
+}
+
+#[test]
+fn test_rule_list_json() {
+    run_json_tests(include_str!("rule_list.json"), |input| {
+        parse_results_to_json(&parse_rule_list(&input), Rule::to_json)
+    });
+}
+
+#[test]
+fn test_one_rule_json() {
+    run_json_tests(include_str!("one_rule.json"), |input| {
+        parse_result_to_json(parse_rule(&input), Rule::to_json)
+    });
+}
+
+#[test]
+fn test_stylesheet_json() {
+    run_json_tests(include_str!("stylesheet.json"), |input| {
+        parse_results_to_json(&parse_stylesheet(&input), Rule::to_json)
+    });
+}
+
+#[test]
+fn test_parse_locations() {
+    let rules = parse_stylesheet("a {}\n  @b c;\n d { e: f }");
+    let locations: Vec<SourceLocation> = rules
+        .iter()
+        .map(|rule| match rule {
+            Ok(Rule::At(rule)) => rule.location.clone(),
+            Ok(Rule::Qualified(rule)) => rule.location.clone(),
+            Err(error) => error.location.clone(),
+        })
+        .collect();
+    assert_eq!(
+        locations,
+        [
+            SourceLocation {
+                line: 1,
+                column: 1,
+                span: Span { start: 0, end: 1 },
+            },
+            SourceLocation {
+                line: 2,
+                column: 3,
+                span: Span { start: 7, end: 9 },
+            },
+            SourceLocation {
+                line: 3,
+                column: 2,
+                span: Span { start: 14, end: 15 },
+            },
+        ]
+    );
+
+    let declarations = parse_declaration_list("a: b;\n  ;c d; e: f(g)");
+    match &declarations[..] {
+        [Ok(DeclarationOrAtRule::Declaration(a)), Err(error), Ok(DeclarationOrAtRule::Declaration(e))] =>
+        {
+            assert_eq!(
+                a.location,
+                SourceLocation {
+                    line: 1,
+                    column: 1,
+                    span: Span { start: 0, end: 1 },
+                }
+            );
+            assert_eq!(
+                error.location,
+                SourceLocation {
+                    line: 2,
+                    column: 6,
+                    span: Span { start: 11, end: 12 },
+                }
+            );
+            assert_eq!(
+                *e.value[0].location(),
+                SourceLocation {
+                    line: 2,
+                    column: 12,
+                    span: Span { start: 17, end: 19 },
+                }
+            );
+        }
+        _ => panic!("unexpected declarations: {:?}", declarations),
+    }
+}
+
+#[test]
+fn test_retokenize() {
+    let css = preprocess(
+        "/* header */\n.a-1 > b[href^=\"x\"] { margin: -1.5e3px 0 +.5% }\n\
+         <!-- @media screen { u+0?? #id { c: url( a.png ) 'str\\\n ing' } } -->\n\
+         \\30 esc é { d: url(bad url) \"unterminated\n}\n",
+    );
+    let replacements = [
+        "", "x", "/*", "*/", "\"", "\n", "-", "{ }", "é", "url(", "9e", "\r", "a\r", "\r\n",
+    ];
+
+    // A simple LCG, so that the edits are the same on every run
+    let mut seed: usize = 12345;
+    let mut next = |bound: usize| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) % bound
+    };
+    let mut input = css;
+    let mut tokens: Vec<Node> = Tokenizer::new(std::sync::Arc::new(input.clone())).collect();
+    for _ in 0..500 {
+        let mut start = next(input.len() + 1);
+        while !input.is_char_boundary(start) {
+            start -= 1;
+        }
+        let mut end = (start + next(8)).min(input.len());
+        while !input.is_char_boundary(end) {
+            end += 1;
+        }
+        // End some edits just before a newline, which a replacement ending in
+        // `\r` joins
+        if next(4) == 0 {
+            end += input[end..].find('\n').unwrap_or(0);
+        }
+        let edit = TextEdit {
+            range: start..end,
+            replacement: replacements[next(replacements.len())].to_string(),
+        };
+
+        let (new_input, new_tokens) = retokenize(&input, &tokens, &edit);
+        let edited = format!("{}{}{}", &input[..start], edit.replacement, &input[end..]);
+        assert_eq!(new_input, preprocess(&edited));
+        let expected: Vec<Node> = Tokenizer::new(std::sync::Arc::new(new_input.clone())).collect();
+        assert!(
+            list_to_json(&new_tokens) == list_to_json(&expected),
+            "tokens differ after replacing {:?} with {:?}",
+            &input[start..end],
+            edit.replacement
+        );
+        assert_eq!(new_tokens, expected, "locations differ");
+        input = new_input;
+        tokens = new_tokens;
+    }
+}
+
+#[test]
+fn test_minify() {
+    let css = "/* header */\n@media screen and (max-width: 600px) {\n  \
+               .nav > a:hover , #aabbcc {\n    margin: 0.50em -00.5px 10.0% +1e3px;\n    \
+               color: #AABBCC; background: #aabbcd url( \"a b.png\" );\n    \
+               --x: #aabbcc;\n  }\n}\n";
+    assert_eq!(
+        minify(css),
+        "@media screen and (max-width:600px){.nav>a:hover,#aabbcc{margin:.5em -.5px 10.0% \
+         1e3px;color:#abc;background:#aabbcd url(\"a b.png\");--x:#aabbcc}}"
+    );
+
+    // Minifying keeps every token other than whitespace and semicolons.
+    let items: Vec<Value> = serde_json::from_str(include_str!("tokens.json")).unwrap();
+    for input in items.iter().step_by(2).map(|item| item.as_str().unwrap()) {
+        let significant = |css: &str| -> Vec<Value> {
+            tokenize(css)
+                .filter(|(token, _)| !matches!(token, Token::WhiteSpace(_) | Token::Semicolon))
+                .map(|(token, _)| token.to_json())
+                .collect()
+        };
+        let minified = minify(input);
+        assert!(
+            significant(&minified) == significant(input),
+            "{:?} minified to {:?}",
+            input,
+            minified
+        );
+        assert_eq!(minify(&minified), minified);
+    }
+}
+
+#[test]
+fn test_tokenize_html() {
+    let html = "<!DOCTYPE html>\r\n<html><head>\r\n<STYLE media=screen>\r\n  a { color: red }\r\n</style>\n\
+                <!-- <style>b {}</style> -->\n\
+                <script>var s = \"<style>c {}</style>\";</script>\n\
+                <p class=x style=\"margin: 0.50px; font-family: &quot;A B&quot;\">x</p>\n\
+                <p STYLE='color: #aabbcc'/></html>";
+    let styles = tokenize_html(html);
+    let sources: Vec<StyleSource> = styles.iter().map(|s| s.source).collect();
+    assert_eq!(
+        sources,
+        [
+            StyleSource::Element,
+            StyleSource::Attribute,
+            StyleSource::Attribute
+        ]
+    );
+    let lines: Vec<&str> = html.split('\n').collect();
+
+    // The location of a token of `len` bytes starting with `text` in the
+    // document
+    let location = |line: usize, text: &str, len: usize| {
+        let column = lines[line - 1].find(text).unwrap() + 1;
+        let start = lines[..line - 1].iter().map(|l| l.len() + 1).sum::<usize>() + column - 1;
+        SourceLocation {
+            line,
+            column,
+            span: Span {
+                start,
+                end: start + len,
+            },
+        }
+    };
+
+    let element = &styles[0];
+    assert_eq!(element.css, "\n  a { color: red }\n");
+    assert_eq!(
+        element.tokens[1],
+        (Token::Ident("a".to_string()), location(4, "a {", 1))
+    );
+    assert_eq!(
+        element.tokens[5],
+        (Token::Ident("color".to_string()), location(4, "color", 5))
+    );
+
+    let attribute = &styles[1];
+    assert_eq!(
+        &html[attribute.range.clone()],
+        "margin: 0.50px; font-family: &quot;A B&quot;"
+    );
+    assert_eq!(attribute.css, "margin: 0.50px; font-family: \"A B\"");
+    assert_eq!(
+        attribute.tokens[0],
+        (Token::Ident("margin".to_string()), location(8, "margin", 6))
+    );
+    assert_eq!(
+        *attribute.tokens.last().unwrap(),
+        (Token::String("A B".to_string()), location(8, "&quot;A", 15))
+    );
+    assert_eq!(attribute.minify(), "margin:.5px;font-family:\"A B\"");
+    assert_eq!(styles[2].minify(), "color:#abc");
+
+    // References are preprocessed like the rest, but lines in the HTML end
+    // only at `\n`, `\r\n` and `\r`, not at form feeds
+    let html = "<p style=\"a:b;&#13;\nc:d&#12;e:f&#xD;&#10;\">\x0C<p style='g:&#0;'>";
+    let styles = tokenize_html(html);
+    assert_eq!(styles[0].css, "a:b;\nc:d\ne:f\n");
+    let c = html.find("c:d").unwrap();
+    assert_eq!(
+        styles[0].tokens[5],
+        (
+            Token::Ident("c".to_string()),
+            SourceLocation {
+                line: 2,
+                column: 1,
+                span: Span {
+                    start: c,
+                    end: c + 1
+                }
+            }
+        )
+    );
+    assert_eq!(styles[1].css, "g:\u{FFFD}");
+    assert_eq!(styles[1].tokens[0].1.line, 2);
+}
+
+fn tokens_of(css: &str) -> Vec<Token> {
+    tokenize(css).map(|(token, _)| token).collect()
+}
+
+#[test]
+fn test_serialize_tokens() {
+    let items: Vec<Value> = serde_json::from_str(include_str!("tokens.json")).unwrap();
+    for input in items.iter().step_by(2).map(|item| item.as_str().unwrap()) {
+        let tokens: Vec<Node> = tokenize(input).collect();
+        let css = serialize_tokens(&tokens);
+        assert!(
+            tokens_of(&css) == tokens_of(input),
+            "{:?} serialized to {:?}",
+            input,
+            css
+        );
+    }
+    let ident = Token::Ident("-1 a".to_string());
+    assert_eq!(ident.to_css(), "-\\31 \\ a");
+}
+
+#[test]
+fn test_display_and_hash() {
+    let nodes: Vec<Node> = tokenize("#a 1.0 1 u\\rl(x) 1.0").collect();
+    let text: Vec<String> = nodes.iter().map(|(token, _)| token.to_string()).collect();
+    assert_eq!(
+        text,
+        ["#a", " ", "1.0", " ", "1", " ", "url(x)", " ", "1.0"]
+    );
+    assert_eq!(nodes[2].1.to_string(), "1:4");
+    assert_eq!(nodes[2].1.span.to_string(), "3..6");
+
+    let mut counts = std::collections::HashMap::new();
+    for (token, _) in &nodes {
+        *counts.entry(token).or_insert(0) += 1;
+    }
+    assert_eq!(counts.len(), 5);
+    assert_eq!(counts[&nodes[2].0], 2);
+
+    match (&nodes[2].0, &nodes[4].0) {
+        (Token::Number(a), Token::Number(b)) => {
+            assert!(a != b && a.approx_eq(b));
+            assert_eq!(a.to_string(), "1.0");
+        }
+        _ => panic!("unexpected tokens"),
+    }
+
+    let mut tokenizer = tokenize("'a");
+    tokenizer.by_ref().for_each(drop);
+    assert_eq!(
+        tokenizer.diagnostics[0].to_string(),
+        "unterminated-string at 0..2: string is not closed before the end of the input"
+    );
+}
+
+proptest! {
+    #[test]
+    fn test_tokenize_total(input in r#"(?s)(.|[0-9]{1,25}|[+\-.eE]|[uU]\+|url\(|\\|/\*|<!--|-->){0,40}"#) {
+        let mut tokenizer = tokenize(&input);
+        tokenizer.by_ref().for_each(drop);
+        let input = preprocess(&input);
+        let lossless = TokenizerOptions { lossless: true, ..Default::default() };
+        tokenize_with_options(&input, lossless).for_each(drop);
+        let legacy = TokenizerOptions { legacy: true, ..Default::default() };
+        tokenize_with_options(&input, legacy).for_each(drop);
+        tokenize_borrowed(&input).for_each(drop);
+    }
+
+    #[test]
+    fn test_serialize_round_trip(input in r#"([a-zA-Z0-9_\-#@.,:;(){}\[\]'"/*!%+<>=~|$^?\\ \n\téu]|url\(|U\+|<!--|-->|e\d){0,30}"#) {
+        let tokens: Vec<Node> = tokenize(&input).collect();
+        let css = serialize_tokens(&tokens);
+        prop_assert_eq!(tokens_of(&css), tokens_of(&input), "{:?} serialized to {:?}", input, css);
+    }
+}
+
+#[test]
+fn test_serialize_round_trip_cdo() {
+    for input in [
+        "<!/**/--",
+        "<!/**/-->",
+        "<!/**/--a(",
+        "</**/!--",
+        "a<!/**/--b",
+    ] {
+        let tokens: Vec<Node> = tokenize(input).collect();
+        let css = serialize_tokens(&tokens);
+        assert_eq!(
+            tokens_of(&css),
+            tokens_of(input),
+            "{:?} serialized to {:?}",
+            input,
+            css
+        );
+    }
+}
@@ -0,0 +1,15 @@
+#![no_main]
+
+use css_lex::*;
+use libfuzzer_sys::fuzz_target;
+use spec_css::spec_tokenize;
+
+// Besides not panicking, the speculative lexer must agree with the sequential
+// one whatever the chunk boundaries land on.
+fuzz_target!(|data: (u8, &str)| {
+    let (num_iters, input) = data;
+    let num_iters = usize::from(num_iters % 16) + 1;
+    let expected: Vec<Node> = tokenize(input).collect();
+    let (_, nodes) = spec_tokenize(input.to_string(), num_iters);
+    assert!(nodes == expected);
+});
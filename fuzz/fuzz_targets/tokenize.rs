@@ -0,0 +1,28 @@
+#![no_main]
+
+use css_lex::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    let mut tokenizer = tokenize(input);
+    let tokens: Vec<Node> = tokenizer.by_ref().collect();
+    for (_, location) in &tokens {
+        source_text(input, location.span);
+    }
+    for diagnostic in &tokenizer.diagnostics {
+        source_text(input, diagnostic.span);
+    }
+
+    let input = preprocess(input);
+    let lossless = TokenizerOptions {
+        lossless: true,
+        ..Default::default()
+    };
+    tokenize_with_options(&input, lossless).for_each(drop);
+    let legacy = TokenizerOptions {
+        legacy: true,
+        ..Default::default()
+    };
+    tokenize_with_options(&input, legacy).for_each(drop);
+    tokenize_borrowed(&input).for_each(drop);
+});
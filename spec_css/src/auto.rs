@@ -0,0 +1,112 @@
+use crate::{spec_tokenize, MIN_CHUNK_SIZE, MIN_SPEC_SIZE};
+use css_lex::*;
+use speculate_lib::SpecStats;
+use std::sync::OnceLock;
+use std::time::Instant;
+use std::{cmp, thread};
+
+// Rough cost of spawning, running and joining one extra thread.
+static SPAWN_COST_NS: f64 = 20_000.0;
+
+// Representative CSS used to measure the lexing cost per byte.
+static CALIBRATION_CSS: &str = "/* header */\n.nav > a[href^=\"http\"]:hover, #main .item-2 {\n  \
+    margin: 0 auto -1.5em; color: #fe0; background: url(img/bg.png) no-repeat;\n  \
+    font: 12px/1.4 \"Helvetica Neue\", sans-serif; width: calc(100% - 20px);\n}\n\
+    @media (max-width: 600px) { .nav { display: none !important } }\n";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChunkReason {
+    // The input is smaller than `MIN_SPEC_SIZE`, so it is lexed sequentially.
+    TooSmall,
+    // One chunk per available thread.
+    Parallelism,
+    // More chunks would be smaller than `MIN_CHUNK_SIZE`.
+    ChunkSize,
+    // More chunks would cost more in thread overhead than they save in lexing.
+    SpawnCost,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkChoice {
+    pub num_iters: usize,
+    pub reason: ChunkReason,
+    pub parallelism: usize,
+    pub ns_per_byte: f64,
+}
+
+#[derive(Debug)]
+pub struct AutoStats {
+    pub spec: SpecStats,
+    pub choice: ChunkChoice,
+}
+
+/**
+ * Measure how long lexing takes per byte of input, in nanoseconds.
+ *
+ * Measured once per process on a built-in sample.
+ */
+pub fn ns_per_byte() -> f64 {
+    static COST: OnceLock<f64> = OnceLock::new();
+    *COST.get_or_init(|| {
+        let css = CALIBRATION_CSS.repeat(64);
+        let start = Instant::now();
+        let count = tokenize(&css).count();
+        let elapsed = start.elapsed().as_nanos() as f64;
+        assert!(count > 0);
+        (elapsed / css.len() as f64).max(f64::MIN_POSITIVE)
+    })
+}
+
+/**
+ * Choose how many chunks to split an input of `len` bytes into.
+ *
+ * Splitting into `n` chunks takes roughly `len * ns_per_byte / n` plus `n`
+ * times the thread overhead, which is smallest when `n` is the square root of
+ * the sequential time over the overhead. This is then capped by the available
+ * parallelism and by `MIN_CHUNK_SIZE`, and the smallest cap is reported.
+ */
+pub fn choose_chunks(len: usize, parallelism: usize, ns_per_byte: f64) -> ChunkChoice {
+    let mut choice = ChunkChoice {
+        num_iters: 1,
+        reason: ChunkReason::TooSmall,
+        parallelism,
+        ns_per_byte,
+    };
+    if len < MIN_SPEC_SIZE {
+        return choice;
+    }
+
+    let by_cost = (len as f64 * ns_per_byte / SPAWN_COST_NS).sqrt() as usize;
+    (choice.num_iters, choice.reason) = [
+        (parallelism, ChunkReason::Parallelism),
+        (len / MIN_CHUNK_SIZE, ChunkReason::ChunkSize),
+        (by_cost, ChunkReason::SpawnCost),
+    ]
+    .into_iter()
+    .min_by_key(|&(limit, _)| limit)
+    .unwrap();
+    choice.num_iters = cmp::max(choice.num_iters, 1);
+    choice
+}
+
+/**
+ * Tokenize `input`, choosing the number of chunks automatically.
+ *
+ * When a single chunk is chosen the input is tokenized sequentially without
+ * spawning any threads.
+ */
+pub fn spec_tokenize_auto(input: String) -> (AutoStats, Vec<Node>) {
+    let parallelism = thread::available_parallelism().map_or(1, |n| n.get());
+    let choice = choose_chunks(input.len(), parallelism, ns_per_byte());
+
+    let (spec, nodes) = if choice.num_iters == 1 {
+        let stats = SpecStats {
+            iters: 1,
+            mispredictions: vec![false],
+        };
+        (stats, tokenize(&input).collect())
+    } else {
+        spec_tokenize(input, choice.num_iters)
+    };
+    (AutoStats { spec, choice }, nodes)
+}
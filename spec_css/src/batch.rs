@@ -0,0 +1,167 @@
+use crate::{concat_chunks, next_token_start, tokenize_chunk};
+use css_lex::*;
+use speculate_lib::SpecStats;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{cmp, thread};
+
+// In the README benchmarks, files under about 2 KiB were mostly slower to
+// tokenize in parallel than sequentially.
+pub static MIN_SPEC_SIZE: usize = 2 * 1024;
+
+// The smallest chunk worth handing to its own worker: a file of
+// `MIN_SPEC_SIZE` split four ways, as in those benchmarks.
+pub static MIN_CHUNK_SIZE: usize = MIN_SPEC_SIZE / 4;
+
+struct ChunkJob {
+    file: usize,
+    chunk: usize,
+    start: usize,
+    upper: usize,
+}
+
+/**
+ * Run `f` on every job using at most `num_workers` threads.
+ *
+ * Workers take the next unclaimed job until none are left, so a few long jobs
+ * do not hold up the rest. Results are returned in job order.
+ */
+fn run_on_workers<J: Send, R: Send>(
+    num_workers: usize,
+    jobs: Vec<J>,
+    f: impl Fn(J) -> R + Sync,
+) -> Vec<R> {
+    let next_job = AtomicUsize::new(0);
+    let mut results: Vec<Option<R>> = jobs.iter().map(|_| None).collect();
+    let jobs: Vec<Mutex<Option<J>>> = jobs.into_iter().map(|job| Mutex::new(Some(job))).collect();
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..cmp::min(num_workers.max(1), jobs.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let idx = next_job.fetch_add(1, Ordering::Relaxed);
+                        if idx >= jobs.len() {
+                            break;
+                        }
+                        let job = jobs[idx].lock().unwrap().take().unwrap();
+                        done.push((idx, f(job)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        for handle in handles {
+            for (idx, res) in handle.join().unwrap() {
+                results[idx] = Some(res);
+            }
+        }
+    });
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+/**
+ * How many chunks to split an input of `len` bytes into.
+ */
+fn chunk_count(len: usize, num_workers: usize) -> usize {
+    if len < MIN_SPEC_SIZE {
+        1
+    } else {
+        cmp::min(num_workers, len / MIN_CHUNK_SIZE).max(1)
+    }
+}
+
+/**
+ * Tokenize many stylesheets sharing a pool of `num_workers` threads.
+ *
+ * Inputs smaller than `MIN_SPEC_SIZE` are tokenized sequentially as a single
+ * job. Larger ones are split into chunks of at least `MIN_CHUNK_SIZE` bytes
+ * whose starts are predicted with `next_token_start`, exactly as in
+ * `spec_tokenize`. Once every chunk has run, the predictions of each input are
+ * checked in order and mispredicted chunks are re-tokenized, as one more job
+ * per input on the same workers, so inputs are checked concurrently.
+ *
+ * Returns the stats and tokens of each input, in the order given.
+ */
+pub fn spec_tokenize_many(inputs: Vec<String>, num_workers: usize) -> Vec<(SpecStats, Vec<Node>)> {
+    let preprocessed = run_on_workers(num_workers, inputs.iter().collect(), |input| {
+        needs_preprocessing(input).then(|| preprocess_with_map(input))
+    });
+    let mut maps = Vec::with_capacity(inputs.len());
+    let inputs: Vec<Arc<String>> = inputs
+        .into_iter()
+        .zip(preprocessed)
+        .map(|(input, preprocessed)| match preprocessed {
+            Some((preprocessed, map)) => {
+                maps.push(Some(map));
+                Arc::new(preprocessed)
+            }
+            None => {
+                maps.push(None);
+                Arc::new(input)
+            }
+        })
+        .collect();
+
+    let mut jobs = Vec::new();
+    let mut num_chunks = Vec::with_capacity(inputs.len());
+    for (file, input) in inputs.iter().enumerate() {
+        let count = chunk_count(input.len(), num_workers);
+        let chunk_size = input.len().div_ceil(count);
+        for chunk in 0..count {
+            jobs.push(ChunkJob {
+                file,
+                chunk,
+                start: chunk * chunk_size,
+                upper: cmp::min((chunk + 1) * chunk_size, input.len()),
+            });
+        }
+        num_chunks.push(count);
+    }
+    // Largest jobs first, so that a big file is not left running on its own
+    // at the end.
+    jobs.sort_by_key(|job| cmp::Reverse(job.upper - job.start));
+
+    let results = run_on_workers(num_workers, jobs.iter().collect(), |job| {
+        let input = &inputs[job.file];
+        let prediction = next_token_start(Arc::clone(input), job.start);
+        let (end, chunk) = tokenize_chunk(Arc::clone(input), prediction, job.upper);
+        (prediction, end, chunk)
+    });
+
+    let mut chunks: Vec<Vec<Option<_>>> = num_chunks
+        .iter()
+        .map(|&count| (0..count).map(|_| None).collect())
+        .collect();
+    for (job, res) in jobs.iter().zip(results) {
+        chunks[job.file][job.chunk] = Some((job.upper, res));
+    }
+
+    let files: Vec<_> = chunks.into_iter().zip(inputs).zip(maps).collect();
+    run_on_workers(num_workers, files, |((results, input), map)| {
+        let mut stats = SpecStats {
+            iters: results.len(),
+            mispredictions: vec![false; results.len()],
+        };
+        let mut previous: Option<usize> = None;
+        let mut file_chunks = Vec::with_capacity(results.len());
+        for (i, chunk) in results.into_iter().enumerate() {
+            let (upper, (prediction, end, chunk)) = chunk.unwrap();
+            let (end, chunk) = match previous {
+                Some(prev) if prev != prediction => {
+                    stats.mispredictions[i] = true;
+                    tokenize_chunk(Arc::clone(&input), prev, upper)
+                }
+                _ => (end, chunk),
+            };
+            previous = Some(end);
+            file_chunks.push(chunk);
+        }
+        let mut nodes = concat_chunks(file_chunks);
+        if let Some(map) = map {
+            map.map_nodes(&mut nodes);
+        }
+        (stats, nodes)
+    })
+}
@@ -0,0 +1,44 @@
+use crate::{concat_chunks_from, spec_tokenize_range, MIN_SPEC_SIZE};
+use css_lex::*;
+use std::sync::Arc;
+
+/**
+ * Like `retokenize`, but re-lex the edited text in `num_iters` speculative
+ * chunks when it is at least `MIN_SPEC_SIZE` bytes long, such as after a
+ * large paste.
+ *
+ * Smaller edits are re-lexed sequentially, as the old tokens are reused after
+ * a few bytes anyway.
+ */
+pub fn spec_retokenize(
+    old_input: &str,
+    old_tokens: &[Node],
+    edit: &TextEdit,
+    num_iters: usize,
+) -> (String, Vec<Node>) {
+    let splice = begin_edit(old_input, old_tokens, edit);
+    let mut tokenizer = splice.tokenizer();
+    if num_iters < 2 || splice.edit_end - splice.position < MIN_SPEC_SIZE {
+        return splice.finish(old_tokens, Vec::new(), tokenizer);
+    }
+
+    let (_, chunks) = spec_tokenize_range(
+        Arc::clone(&splice.input),
+        splice.position,
+        splice.edit_end,
+        num_iters,
+    );
+    // Leave the tokenizer where the last chunk stopped, so `finish` carries on
+    // from there.
+    let mut state = tokenizer.checkpoint();
+    for chunk in &chunks {
+        state.position = chunk.end;
+        state.line += chunk.newlines;
+        if chunk.newlines > 0 {
+            state.last_line_start = chunk.last_line_start;
+        }
+    }
+    tokenizer.reset_to(state);
+    let relexed = concat_chunks_from(chunks, splice.line - 1, splice.last_line_start);
+    splice.finish(old_tokens, relexed, tokenizer)
+}
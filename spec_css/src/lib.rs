@@ -1,46 +1,32 @@
+pub use auto::*;
+pub use batch::*;
+pub use incremental::*;
+pub use mapped::*;
+pub use minify::*;
+pub use report::*;
+pub use rules::*;
+pub use verify::*;
+
+pub mod auto;
+pub mod batch;
+pub mod incremental;
+pub mod mapped;
+pub mod minify;
+pub mod report;
+pub mod rules;
+pub mod verify;
+
 use css_lex::*;
 use speculate_lib::*;
-use std::sync::{mpsc, Arc};
+use std::cmp;
+use std::sync::Arc;
 use std::thread;
-use std::{cmp, vec};
 
 static LOOKBACK: usize = 10;
 
-/**
- * Collects the loop body results into a 2d vector.
- *
- * Each loop body sends its index and an optional result type. If the message
- * received over the `port` is `None`, then stop listening and push the result
- * over `chan`. If the message received is `Some(i, None)`, then clear the
- * `i`-th result vector. If the message is `Some(i, Some(t))`, then add `t` to
- * the `i`-th result vector.
- */
-fn spawn_result_collector<T: Send + Clone + 'static>(
-    receiver: mpsc::Receiver<Option<(usize, Option<Vec<T>>)>>,
-    sender: mpsc::Sender<Vec<T>>,
-    size: usize,
-) {
-    thread::spawn(move || {
-        let mut results = vec![Vec::new(); size];
-        while let Ok(message) = receiver.recv() {
-            match message {
-                None => break, // Break on receiving a None, indicating no more messages.
-                Some((idx, Some(val))) => {
-                    // Append new values to the existing vector instead of replacing it
-                    results[idx].extend(val);
-                }
-                Some((idx, None)) => {
-                    // Clear the vector at index idx
-                    results[idx].clear();
-                }
-            }
-        }
-        // Flatten the results and collect them into a single vector
-        let flattened_results = results.into_iter().flatten().collect();
-        // Send the final results
-        sender.send(flattened_results).unwrap();
-    });
-}
+// A rough guess at the average token length, used to size each chunk's buffer
+// up front.
+static BYTES_PER_TOKEN: usize = 4;
 
 /**
  * Find the start of the next token at or after `start`.
@@ -50,54 +36,278 @@ fn spawn_result_collector<T: Send + Clone + 'static>(
  *
  * Assumes `input` has already been preprocessed.
  */
-pub fn next_token_start(input: Arc<String>, start: usize) -> usize {
+pub fn next_token_start<S: Source>(input: Arc<S>, start: usize) -> usize {
+    let position = lookback_start(&input, start);
     let mut tokenizer = Tokenizer::new(input);
-    tokenizer.position = if start < LOOKBACK {
-        0
-    } else {
-        start - LOOKBACK
-    };
+    tokenizer.reset_to(TokenizerState::at_line_start(position));
 
     while tokenizer.position < start && tokenizer.next().is_some() {}
 
     tokenizer.position
 }
 
-pub fn spec_tokenize(input: String, num_iters: usize) -> (SpecStats, Vec<Node>) {
-    let input = preprocess(&input); // Assuming preprocess() adapts to String -> String
+/**
+ * Where `next_token_start` begins lexing: `LOOKBACK` bytes before `start`,
+ * moved back to a character boundary.
+ */
+fn lookback_start(input: &str, start: usize) -> usize {
+    let mut position = start.saturating_sub(LOOKBACK);
+    while !input.is_char_boundary(position) {
+        position -= 1;
+    }
+    position
+}
+
+/**
+ * Split `input` into `num_chunks` byte ranges for preprocessing.
+ *
+ * Each boundary is moved forward to a character boundary, and past the `\n`
+ * of a `\r\n` pair so the pair is still seen as a single newline.
+ */
+fn preprocess_bounds(input: &str, num_chunks: usize) -> Vec<usize> {
+    let bytes = input.as_bytes();
+    let chunk_size = input.len().div_ceil(num_chunks.max(1));
+    let mut bounds = vec![0];
+    for idx in 1..num_chunks {
+        let mut bound = cmp::max(idx * chunk_size, *bounds.last().unwrap());
+        while bound < input.len() && !input.is_char_boundary(bound) {
+            bound += 1;
+        }
+        if bound > 0 && bound < input.len() && bytes[bound - 1] == b'\r' && bytes[bound] == b'\n' {
+            bound += 1;
+        }
+        bounds.push(cmp::min(bound, input.len()));
+    }
+    bounds.push(input.len());
+    bounds
+}
+
+/**
+ * Preprocess `input` in `num_chunks` parallel pieces.
+ *
+ * Produces the same result as `preprocess`. If no piece needs any
+ * replacement, `input` is returned as is without being copied.
+ */
+pub fn par_preprocess(input: String, num_chunks: usize) -> String {
+    par_preprocess_mapped(input, num_chunks).0
+}
+
+/**
+ * Like `par_preprocess`, also returning where the offsets of the result came
+ * from in `input` if anything was replaced.
+ */
+fn par_preprocess_mapped(input: String, num_chunks: usize) -> (String, Option<OffsetMap>) {
+    match par_preprocess_str(&input, num_chunks) {
+        Some((preprocessed, map)) => (preprocessed, Some(map)),
+        None => (input, None),
+    }
+}
+
+/**
+ * Like `par_preprocess_mapped`, but returns `None` instead of a copy when
+ * `input` does not need any replacements.
+ */
+fn par_preprocess_str(input: &str, num_chunks: usize) -> Option<(String, OffsetMap)> {
+    let bounds = preprocess_bounds(input, num_chunks);
+    let pieces: Vec<&str> = bounds.windows(2).map(|r| &input[r[0]..r[1]]).collect();
+
+    // The preprocessed length of each piece that needs any replacement
+    let lens: Vec<Option<usize>> = thread::scope(|scope| {
+        let handles: Vec<_> = pieces
+            .iter()
+            .map(|&piece| {
+                scope.spawn(move || needs_preprocessing(piece).then(|| preprocessed_len(piece)))
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    if lens.iter().all(Option::is_none) {
+        return None;
+    }
+
+    // Each piece is written straight into its own part of the result.
+    let mut buffer = vec![
+        0;
+        lens.iter()
+            .zip(&pieces)
+            .map(|(len, piece)| len.unwrap_or(piece.len()))
+            .sum()
+    ];
+    let mut outs = Vec::with_capacity(pieces.len());
+    let mut rest = &mut buffer[..];
+    for (len, piece) in lens.iter().zip(&pieces) {
+        let (out, tail) = rest.split_at_mut(len.unwrap_or(piece.len()));
+        outs.push(out);
+        rest = tail;
+    }
+    let piece_maps: Vec<Option<OffsetMap>> = thread::scope(|scope| {
+        let handles: Vec<_> = pieces
+            .iter()
+            .zip(&lens)
+            .zip(outs)
+            .map(|((&piece, len), out)| {
+                scope.spawn(move || match len {
+                    Some(_) => Some(preprocess_into(piece, out)),
+                    None => {
+                        out.copy_from_slice(piece.as_bytes());
+                        None
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut map = OffsetMap::new(0);
+    let mut offset = 0;
+    for ((range, piece_map), len) in bounds.windows(2).zip(piece_maps).zip(lens) {
+        match piece_map {
+            Some(piece_map) => map.append(&piece_map, offset, range[0]),
+            None => map.anchor(offset, range[0]),
+        }
+        offset += len.unwrap_or(range[1] - range[0]);
+    }
+    // Every piece starts and ends on a character boundary of `input` and is
+    // either copied from it or preprocessed, which only swaps characters for
+    // others, so the buffer is valid UTF-8.
+    let result = unsafe { String::from_utf8_unchecked(buffer) };
+    Some((result, map))
+}
+
+/**
+ * The output of one loop body.
+ *
+ * The tokenizer of a chunk cannot know which line it starts on, so locations
+ * are relative to the chunk's start: line 1 is the line `start` is on, and
+ * columns on that line count from `start`. `concat_chunks` fixes them up.
+ */
+struct Chunk {
+    start: usize,
+    // Where the tokenizer stopped, which is where the next chunk starts
+    end: usize,
+    nodes: Vec<Node>,
+    newlines: usize,
+    last_line_start: usize,
+}
+
+fn iter_size(len: usize, num_iters: usize) -> usize {
+    len.div_ceil(num_iters.max(1))
+}
+
+/**
+ * Run the speculative fold over already preprocessed `input`, returning the
+ * chunks as they were produced.
+ */
+fn spec_tokenize_chunks<S: Source + Send + Sync + 'static>(
+    input: Arc<S>,
+    num_iters: usize,
+) -> (SpecStats, Vec<Chunk>) {
     let css_len = input.len();
-    let str_arc = Arc::new(input.clone());
+    spec_tokenize_range(input, 0, css_len, num_iters)
+}
 
-    let iter_size: usize = (css_len + num_iters - 1).div_ceil(num_iters); // round up
+/**
+ * Run the speculative fold over the bytes of `input` from `start` up to at
+ * least `end`. `start` must be the start of a token. Zero iterations are
+ * treated as one.
+ */
+fn spec_tokenize_range<S: Source + Send + Sync + 'static>(
+    input: Arc<S>,
+    start: usize,
+    end: usize,
+    num_iters: usize,
+) -> (SpecStats, Vec<Chunk>) {
+    let num_iters = num_iters.max(1);
+    let predictor_arc = Arc::clone(&input);
 
-    let (tx, rx) = mpsc::channel();
-    let (res_tx, res_rx) = mpsc::channel();
+    let iter_size = iter_size(end - start, num_iters);
 
     // LOOP_BODY
-    let body_tx = tx.clone();
 
     let loop_body = move |idx: usize, token_start: &usize| {
-        let upper = std::cmp::min((idx + 1) * iter_size, css_len);
-        let mut tokenizer = Tokenizer::new(Arc::clone(&str_arc));
-        tokenizer.position = *token_start;
-        let mut results: Vec<Node> = Vec::with_capacity(10);
-        body_tx.send(Some((idx, None))).unwrap();
-        while tokenizer.position < upper {
-            match tokenizer.next() {
-                Some(node) => results.push(node),
-                None => break,
-            }
-        }
-        body_tx.send(Some((idx, Some(results)))).unwrap();
-        tokenizer.position
+        let upper = cmp::min(start + (idx + 1) * iter_size, end);
+        tokenize_chunk(Arc::clone(&input), *token_start, upper)
     };
-    let str_arc = Arc::new(input);
 
     // PREDICTOR
 
-    let predictor = move |idx| next_token_start(Arc::clone(&str_arc), idx * iter_size);
-    spawn_result_collector(rx, res_tx, num_iters);
-    let res = specfold(num_iters, loop_body, predictor);
-    tx.send(None).unwrap();
-    (res, res_rx.recv().unwrap())
+    let predictor = move |idx| match idx {
+        0 => start,
+        _ => next_token_start(Arc::clone(&predictor_arc), start + idx * iter_size),
+    };
+    specfold_results(num_iters, loop_body, predictor)
+}
+
+/**
+ * Tokenize `input` in `num_iters` speculative chunks. Gives the same tokens
+ * and locations as `tokenize`.
+ */
+pub fn spec_tokenize(input: String, num_iters: usize) -> (SpecStats, Vec<Node>) {
+    let (input, map) = par_preprocess_mapped(input, num_iters);
+    let (res, chunks) = spec_tokenize_chunks(Arc::new(input), num_iters);
+    let mut nodes = concat_chunks(chunks);
+    if let Some(map) = map {
+        map.map_nodes(&mut nodes);
+    }
+    (res, nodes)
+}
+
+/**
+ * Tokenize from `token_start` until reaching or passing `upper`.
+ *
+ * Returns the position the next chunk should start at along with the chunk.
+ */
+fn tokenize_chunk<S: Source>(input: Arc<S>, token_start: usize, upper: usize) -> (usize, Chunk) {
+    let mut tokenizer = Tokenizer::new(input);
+    tokenizer.reset_to(TokenizerState::at_line_start(token_start));
+    let mut nodes: Vec<Node> =
+        Vec::with_capacity(upper.saturating_sub(token_start) / BYTES_PER_TOKEN);
+    while tokenizer.position < upper {
+        match tokenizer.next() {
+            Some(node) => nodes.push(node),
+            None => break,
+        }
+    }
+    let chunk = Chunk {
+        start: token_start,
+        end: tokenizer.position,
+        nodes,
+        newlines: tokenizer.line - 1,
+        last_line_start: tokenizer.last_line_start,
+    };
+    (tokenizer.position, chunk)
+}
+
+/**
+ * Concatenate the per-chunk token lists into a single, exactly sized vector,
+ * making every location absolute along the way.
+ */
+fn concat_chunks(chunks: Vec<Chunk>) -> Vec<Node> {
+    concat_chunks_from(chunks, 0, 0)
+}
+
+/**
+ * Like `concat_chunks`, for chunks starting after `lines_before` newlines on
+ * the line starting at `line_start`.
+ */
+fn concat_chunks_from(
+    chunks: Vec<Chunk>,
+    mut lines_before: usize,
+    mut line_start: usize,
+) -> Vec<Node> {
+    let mut nodes = Vec::with_capacity(chunks.iter().map(|c| c.nodes.len()).sum());
+    for chunk in chunks {
+        for (token, mut location) in chunk.nodes {
+            if location.line == 1 {
+                location.column += chunk.start - line_start;
+            }
+            location.line += lines_before;
+            nodes.push((token, location));
+        }
+        if chunk.newlines > 0 {
+            line_start = chunk.last_line_start;
+        }
+        lines_before += chunk.newlines;
+    }
+    nodes
 }
@@ -0,0 +1,171 @@
+use crate::{concat_chunks, par_preprocess_str, spec_tokenize_chunks};
+use css_lex::*;
+use memmap2::Mmap;
+use speculate_lib::SpecStats;
+use std::fs::File;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use std::{cmp, error, fmt, io, str, thread};
+
+#[derive(Debug)]
+pub enum InputError {
+    Io(io::Error),
+    // The input is not valid UTF-8, starting at this byte offset
+    Utf8 { offset: usize },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::Io(err) => write!(f, "could not read input: {}", err),
+            InputError::Utf8 { offset } => write!(f, "invalid UTF-8 at byte {}", offset),
+        }
+    }
+}
+
+impl error::Error for InputError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            InputError::Io(err) => Some(err),
+            InputError::Utf8 { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for InputError {
+    fn from(err: io::Error) -> Self {
+        InputError::Io(err)
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for memmap2::Mmap {}
+    impl Sealed for Vec<u8> {}
+    impl Sealed for Box<[u8]> {}
+    impl Sealed for &[u8] {}
+}
+
+/**
+ * Byte buffers which give the same bytes every time they are read, so they
+ * stay valid UTF-8 once checked.
+ *
+ * Sealed, since `Utf8Bytes` relies on it for soundness: any other
+ * `AsRef<[u8]>` could return different bytes after being validated.
+ */
+pub trait StableBytes: AsRef<[u8]> + sealed::Sealed {}
+
+impl<B: AsRef<[u8]> + sealed::Sealed> StableBytes for B {}
+
+/**
+ * Bytes which have been checked to be valid UTF-8, usable as a tokenizer
+ * `Source` without copying them into a `String`.
+ */
+pub struct Utf8Bytes<B>(B);
+
+impl<B: StableBytes> Utf8Bytes<B> {
+    /**
+     * Validate `bytes`, checking `num_chunks` pieces in parallel.
+     */
+    pub fn new(bytes: B, num_chunks: usize) -> Result<Utf8Bytes<B>, InputError> {
+        par_validate_utf8(bytes.as_ref(), num_chunks)?;
+        Ok(Utf8Bytes(bytes))
+    }
+}
+
+impl<B: StableBytes> Deref for Utf8Bytes<B> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        // Validated in `Utf8Bytes::new`. `B` is never handed out mutably, and
+        // `StableBytes` only covers buffers whose contents cannot change
+        // behind a shared reference.
+        unsafe { str::from_utf8_unchecked(self.0.as_ref()) }
+    }
+}
+
+/**
+ * Check that `bytes` is valid UTF-8 in `num_chunks` parallel pieces.
+ *
+ * Each boundary is moved forward past any continuation bytes, so that no
+ * character is split. On failure, reports the offset of the first invalid
+ * byte in the whole input.
+ */
+fn par_validate_utf8(bytes: &[u8], num_chunks: usize) -> Result<(), InputError> {
+    let chunk_size = bytes.len().div_ceil(num_chunks.max(1));
+    let mut bounds = vec![0];
+    for idx in 1..num_chunks {
+        let mut bound = cmp::max(idx * chunk_size, *bounds.last().unwrap());
+        // A character is at most 4 bytes, so look past at most 3 continuations
+        for _ in 0..3 {
+            if bound < bytes.len() && bytes[bound] & 0xC0 == 0x80 {
+                bound += 1;
+            }
+        }
+        bounds.push(cmp::min(bound, bytes.len()));
+    }
+    bounds.push(bytes.len());
+
+    let errors: Vec<Option<usize>> = thread::scope(|scope| {
+        let handles: Vec<_> = bounds
+            .windows(2)
+            .map(|range| {
+                let (start, end) = (range[0], range[1]);
+                scope.spawn(move || {
+                    str::from_utf8(&bytes[start..end])
+                        .err()
+                        .map(|err| start + err.valid_up_to())
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    match errors.into_iter().flatten().next() {
+        Some(offset) => Err(InputError::Utf8 { offset }),
+        None => Ok(()),
+    }
+}
+
+/**
+ * Tokenize raw bytes, such as the contents of a memory-mapped file.
+ *
+ * The bytes are validated and preprocessed in parallel. They are only copied
+ * when preprocessing has to replace something, in which case the preprocessed
+ * copy is the only one made.
+ */
+pub fn spec_tokenize_bytes<B: StableBytes + Send + Sync + 'static>(
+    bytes: B,
+    num_iters: usize,
+) -> Result<(SpecStats, Vec<Node>), InputError> {
+    let input = Utf8Bytes::new(bytes, num_iters)?;
+    match par_preprocess_str(&input, num_iters) {
+        Some((preprocessed, map)) => {
+            let (stats, chunks) = spec_tokenize_chunks(Arc::new(preprocessed), num_iters);
+            let mut nodes = concat_chunks(chunks);
+            map.map_nodes(&mut nodes);
+            Ok((stats, nodes))
+        }
+        None => {
+            let (stats, chunks) = spec_tokenize_chunks(Arc::new(input), num_iters);
+            Ok((stats, concat_chunks(chunks)))
+        }
+    }
+}
+
+/**
+ * Memory-map the file at `path` and tokenize it with `spec_tokenize_bytes`.
+ *
+ * The file must not be modified while it is being tokenized.
+ */
+pub fn spec_tokenize_path(
+    path: impl AsRef<Path>,
+    num_iters: usize,
+) -> Result<(SpecStats, Vec<Node>), InputError> {
+    let file = File::open(path)?;
+    // Safety: as documented above, the caller must not modify the file while
+    // it is mapped.
+    let map = unsafe { Mmap::map(&file)? };
+    spec_tokenize_bytes(map, num_iters)
+}
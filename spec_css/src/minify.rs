@@ -0,0 +1,23 @@
+use crate::spec_tokenize_rules;
+use css_lex::*;
+use speculate_lib::SpecStats;
+use std::thread;
+
+/**
+ * Minify `input` in `num_iters` parallel chunks.
+ *
+ * The stylesheet is tokenized with `spec_tokenize_rules`, so every chunk holds
+ * whole top-level rules and can be minified on its own. The minified chunks
+ * are then joined, giving the same result as `minify`.
+ */
+pub fn spec_minify(input: String, num_iters: usize) -> (SpecStats, String) {
+    let (stats, chunks) = spec_tokenize_rules(input, num_iters);
+    let pieces: Vec<String> = thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .iter()
+            .map(|chunk| scope.spawn(|| minify_tokens(chunk)))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    (stats, pieces.concat())
+}
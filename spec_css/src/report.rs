@@ -0,0 +1,146 @@
+use crate::{
+    concat_chunks, iter_size, lookback_start, next_token_start, par_preprocess_mapped,
+    spec_tokenize_chunks,
+};
+use css_lex::*;
+use speculate_lib::SpecStats;
+use std::fmt;
+use std::sync::Arc;
+
+// The most bytes of source shown for each mispredicted chunk.
+static SNIPPET_LEN: usize = 80;
+
+/**
+ * What the predictor began lexing inside of when it guessed a chunk's start
+ * wrong.
+ */
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MissCause {
+    Comment,
+    String,
+    Url,
+    // A token containing a backslash escape, other than a string or url
+    Escape,
+    Other,
+}
+
+/**
+ * How one chunk's prediction went. Offsets are in terms of the text passed to
+ * `spec_tokenize_report`, like the locations of its tokens.
+ */
+#[derive(Debug)]
+pub struct ChunkReport {
+    pub chunk: usize,
+    // Byte offset the chunk was split at, before searching for a token start
+    pub chunk_offset: usize,
+    pub predicted_start: usize,
+    // Where the previous chunk ended, which is where this one had to start
+    pub actual_start: usize,
+    // Only set for mispredicted chunks
+    pub cause: Option<MissCause>,
+    // The source from where the predictor began lexing, for mispredicted
+    // chunks
+    pub snippet: String,
+}
+
+#[derive(Debug)]
+pub struct MispredictionReport {
+    pub spec: SpecStats,
+    pub chunks: Vec<ChunkReport>,
+}
+
+impl fmt::Display for MispredictionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chunk in &self.chunks {
+            write!(
+                f,
+                "chunk {} split at byte {}: predicted start {}, actual {}",
+                chunk.chunk, chunk.chunk_offset, chunk.predicted_start, chunk.actual_start
+            )?;
+            if let Some(cause) = chunk.cause {
+                write!(f, ", missed in {:?}: {:?}", cause, chunk.snippet)?;
+            }
+            writeln!(f)?;
+        }
+        let missed = self.spec.mispredictions.iter().filter(|&&m| m).count();
+        write!(f, "{} of {} chunks mispredicted", missed, self.chunks.len())
+    }
+}
+
+/**
+ * Find what the sequential tokenizer was in the middle of at `position`.
+ *
+ * `nodes` must be the tokens of `input`.
+ */
+fn cause_at(input: &str, nodes: &[Node], position: usize) -> MissCause {
+    let count = nodes.partition_point(|(_, location)| location.span.start <= position);
+    let (token, location) = match count.checked_sub(1) {
+        Some(idx) => &nodes[idx],
+        // Only comments come before the first token.
+        None => return MissCause::Comment,
+    };
+
+    // Anything between a token and the next one is a comment.
+    if position >= location.span.end {
+        return MissCause::Comment;
+    }
+    match token {
+        Token::String(_) | Token::BadString => MissCause::String,
+        Token::URL(_) | Token::BadURL => MissCause::Url,
+        _ if source_text(input, location.span).contains('\\') => MissCause::Escape,
+        _ => MissCause::Other,
+    }
+}
+
+/**
+ * Like `spec_tokenize`, but also report where each chunk was predicted to
+ * start, where it actually started, and for mispredicted chunks what the
+ * predictor started lexing inside of.
+ *
+ * Meant for finding out why a stylesheet is slower to tokenize in parallel,
+ * e.g. whether `LOOKBACK` is too short for its comments or strings.
+ */
+pub fn spec_tokenize_report(input: String, num_iters: usize) -> (MispredictionReport, Vec<Node>) {
+    let (input, map) = par_preprocess_mapped(input, num_iters);
+    let input = Arc::new(input);
+    let (spec, chunks) = spec_tokenize_chunks(Arc::clone(&input), num_iters);
+    let actual_starts: Vec<usize> = chunks.iter().map(|c| c.start).collect();
+    let mut nodes = concat_chunks(chunks);
+
+    let iter_size = iter_size(input.len(), num_iters);
+    let chunks = actual_starts
+        .into_iter()
+        .enumerate()
+        .map(|(chunk, actual_start)| {
+            let chunk_offset = chunk * iter_size;
+            let predicted_start = next_token_start(Arc::clone(&input), chunk_offset);
+            let (cause, snippet) = if spec.mispredictions[chunk] {
+                let from = lookback_start(&input, chunk_offset);
+                let mut to = std::cmp::min(from + SNIPPET_LEN, input.len());
+                while !input.is_char_boundary(to) {
+                    to -= 1;
+                }
+                let cause = cause_at(&input, &nodes, from);
+                (Some(cause), input[from..to].to_string())
+            } else {
+                (None, String::new())
+            };
+            let original = |offset| match &map {
+                Some(map) => map.original_offset(offset),
+                None => offset,
+            };
+            ChunkReport {
+                chunk,
+                chunk_offset: original(chunk_offset),
+                predicted_start: original(predicted_start),
+                actual_start: original(actual_start),
+                cause,
+                snippet,
+            }
+        })
+        .collect();
+    if let Some(map) = map {
+        map.map_nodes(&mut nodes);
+    }
+    (MispredictionReport { spec, chunks }, nodes)
+}
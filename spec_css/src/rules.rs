@@ -0,0 +1,284 @@
+use crate::{concat_chunks, iter_size, par_preprocess_mapped, Chunk, BYTES_PER_TOKEN};
+use css_lex::*;
+use speculate_lib::*;
+use std::cmp;
+use std::sync::Arc;
+use std::thread;
+
+// How far back `ends_top_level_rule` looks from a `}`
+static RULE_LOOKBACK: usize = 4096;
+
+/**
+ * Whether the byte at `position` is escaped by an odd number of backslashes.
+ */
+fn is_escaped(bytes: &[u8], position: usize) -> bool {
+    bytes[..position]
+        .iter()
+        .rev()
+        .take_while(|&&b| b == b'\\')
+        .count()
+        % 2
+        == 1
+}
+
+/**
+ * Guess whether the `}` at `position` closes a top-level block by scanning
+ * back at most `RULE_LOOKBACK` bytes from it.
+ *
+ * The scan skips comments and strings and matches braces. The `}` is rejected
+ * if it turns out to be inside a comment or a string, if the scan finds an
+ * unmatched `{` before the block the `}` closes, or if it runs out before
+ * finding the start of that block. Strings cannot span lines, so a quote with
+ * no matching quote before it on its line is taken to open a string holding
+ * the `}`.
+ */
+fn ends_top_level_rule(bytes: &[u8], position: usize) -> bool {
+    let limit = position.saturating_sub(RULE_LOOKBACK);
+    // Blocks closed between the scan and `position`, including the one the
+    // `}` closes until its `{` is found
+    let mut depth: usize = 1;
+    let mut matched = false;
+    let mut idx = position;
+    while idx > limit {
+        idx -= 1;
+        match bytes[idx] {
+            b'/' if idx > limit && bytes[idx - 1] == b'*' => {
+                match bytes[limit..idx - 1].windows(2).rposition(|w| w == b"/*") {
+                    Some(start) => idx = limit + start,
+                    None => return matched,
+                }
+            }
+            b'*' if idx > limit && bytes[idx - 1] == b'/' => return false,
+            quote @ (b'"' | b'\'') if !is_escaped(bytes, idx) => loop {
+                if idx == limit {
+                    return matched;
+                }
+                idx -= 1;
+                match bytes[idx] {
+                    b'\n' => return false,
+                    b if b == quote && !is_escaped(bytes, idx) => break,
+                    _ => (),
+                }
+            },
+            b'}' => depth += 1,
+            b'{' if depth == 0 => return false,
+            b'{' => {
+                depth -= 1;
+                matched |= depth == 0;
+            }
+            _ => (),
+        }
+    }
+    matched
+}
+
+/**
+ * Guess where the next top-level rule starts, at or after `start`.
+ *
+ * Returns the position just after the first `}` ending at or after `start`
+ * which `ends_top_level_rule` accepts, or the end of `input` if there is
+ * none. Since the scan only looks back a bounded distance and does not
+ * tokenize, the guess can still be wrong, which `spec_tokenize_rules` catches
+ * when validating.
+ */
+pub fn next_rule_start<S: Source>(input: Arc<S>, start: usize) -> usize {
+    let bytes = input.as_bytes();
+    let mut position = start.saturating_sub(1);
+    while position < bytes.len() {
+        if bytes[position] == b'}' && ends_top_level_rule(bytes, position) {
+            return position + 1;
+        }
+        position += 1;
+    }
+    input.len()
+}
+
+/**
+ * Account for `token` in the stack of `open` blocks, holding the closing
+ * bracket of each, innermost last. Returns whether `token` closes a top-level
+ * block.
+ *
+ * Brackets are matched as in CSS Syntax: a closing bracket only closes the
+ * innermost open block if it is of the same kind, and is otherwise an ordinary
+ * token.
+ */
+fn closes_top_level_block(open: &mut Vec<Token>, token: &Token) -> bool {
+    let closed = open.last() == Some(token);
+    match token {
+        Token::LeftCurlyBracket => open.push(Token::RightCurlyBracket),
+        Token::LeftBracket => open.push(Token::RightBracket),
+        Token::LeftParen | Token::Function(_) => open.push(Token::RightParen),
+        _ if closed => {
+            open.pop();
+        }
+        _ => (),
+    }
+    closed && open.is_empty()
+}
+
+/**
+ * Tokenize from `token_start`, which is assumed to be between two top-level
+ * rules, until just after the first `}` which ends a top-level rule and ends
+ * at or after `upper`.
+ */
+fn tokenize_rules_chunk<S: Source>(
+    input: Arc<S>,
+    token_start: usize,
+    upper: usize,
+) -> (usize, Chunk) {
+    let mut tokenizer = Tokenizer::new(Arc::clone(&input));
+    tokenizer.reset_to(TokenizerState::at_line_start(token_start));
+    let mut nodes: Vec<Node> =
+        Vec::with_capacity(upper.saturating_sub(token_start) / BYTES_PER_TOKEN);
+    let mut open: Vec<Token> = Vec::new();
+    while let Some(node) = tokenizer.next() {
+        let at_boundary = closes_top_level_block(&mut open, &node.0)
+            && node.0 == Token::RightCurlyBracket
+            && tokenizer.position >= upper;
+        nodes.push(node);
+        if at_boundary {
+            break;
+        }
+    }
+    let chunk = Chunk {
+        start: token_start,
+        end: tokenizer.position,
+        nodes,
+        newlines: tokenizer.line - 1,
+        last_line_start: tokenizer.last_line_start,
+    };
+    (tokenizer.position, chunk)
+}
+
+/**
+ * Tokenize `input` in `num_iters` chunks split between top-level rules.
+ *
+ * Each chunk is predicted to start just after a closing brace found by
+ * `next_rule_start`, and tokenizes whole rules until passing the next chunk's
+ * share of the input. A prediction is only accepted if the previous chunk
+ * really ended there after a top-level rule, so the tokens are the same as
+ * `tokenize` would give, but each chunk holds a run of complete top-level
+ * rules which can be processed on its own.
+ *
+ * Returns the tokens of each chunk, with absolute locations. Zero iterations
+ * are treated as one.
+ */
+pub fn spec_tokenize_rules(input: String, num_iters: usize) -> (SpecStats, Vec<Vec<Node>>) {
+    let num_iters = num_iters.max(1);
+    let (input, map) = par_preprocess_mapped(input, num_iters);
+    let input = Arc::new(input);
+    let css_len = input.len();
+    let predictor_arc = Arc::clone(&input);
+
+    let iter_size = iter_size(css_len, num_iters);
+
+    // LOOP_BODY
+
+    let loop_body = move |idx: usize, token_start: &usize| {
+        let upper = cmp::min((idx + 1) * iter_size, css_len);
+        tokenize_rules_chunk(Arc::clone(&input), *token_start, upper)
+    };
+
+    // PREDICTOR
+
+    let predictor = move |idx| match idx {
+        0 => 0,
+        _ => next_rule_start(Arc::clone(&predictor_arc), idx * iter_size),
+    };
+    let (stats, chunks) = specfold_results(num_iters, loop_body, predictor);
+
+    let lens: Vec<usize> = chunks.iter().map(|c| c.nodes.len()).collect();
+    let mut nodes = concat_chunks(chunks);
+    if let Some(map) = map {
+        map.map_nodes(&mut nodes);
+    }
+    let mut nodes = nodes.into_iter();
+    let rules = lens
+        .into_iter()
+        .map(|len| nodes.by_ref().take(len).collect())
+        .collect();
+    (stats, rules)
+}
+
+/**
+ * Split a run of tokens into top-level rules.
+ *
+ * A rule ends with the `}` closing its block, or with a top-level `;` if it
+ * starts with an at-keyword. Whitespace, comments, `<!--` and `-->` between
+ * rules are dropped, and anything left at the end forms a last, unfinished
+ * rule. This finds the same rules as consuming a list of rules would, without
+ * building anything from them.
+ */
+pub fn split_rules(nodes: Vec<Node>) -> Vec<Vec<Node>> {
+    let mut rules = Vec::new();
+    let mut rule: Vec<Node> = Vec::new();
+    let mut open: Vec<Token> = Vec::new();
+    for node in nodes {
+        if rule.is_empty()
+            && matches!(
+                node.0,
+                Token::WhiteSpace(_) | Token::Comment(_) | Token::CDO | Token::CDC
+            )
+        {
+            continue;
+        }
+        let ends_at_rule = node.0 == Token::Semicolon
+            && open.is_empty()
+            && matches!(rule.first(), Some((Token::AtKeyword(_), _)));
+        let ends_rule = closes_top_level_block(&mut open, &node.0)
+            && node.0 == Token::RightCurlyBracket
+            || ends_at_rule;
+        rule.push(node);
+        if ends_rule {
+            rules.push(std::mem::take(&mut rule));
+        }
+    }
+    if !rule.is_empty() {
+        rules.push(rule);
+    }
+    rules
+}
+
+/**
+ * Run `parse` on the chunks of `spec_tokenize_rules` in parallel and join the
+ * results in order.
+ *
+ * Every chunk holds whole top-level rules, so anything which consumes a list
+ * of rules, such as `split_rules`, gives the same result as when run on the
+ * tokens of the whole stylesheet.
+ */
+pub fn spec_parse_rules<R: Send>(
+    input: String,
+    num_iters: usize,
+    parse: impl Fn(Vec<Node>) -> Vec<R> + Sync,
+) -> (SpecStats, Vec<R>) {
+    let (stats, chunks) = spec_tokenize_rules(input, num_iters);
+    let parse = &parse;
+    let rules = thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(move || parse(chunk)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    (stats, rules)
+}
+
+/**
+ * Parse `input` as a stylesheet, parsing the chunks of `spec_tokenize_rules`
+ * in parallel.
+ *
+ * Since every chunk starts between two top-level rules, the result is the same
+ * as `parse_stylesheet`.
+ */
+pub fn spec_parse_stylesheet(
+    input: String,
+    num_iters: usize,
+) -> (SpecStats, Vec<Result<Rule, ParseError>>) {
+    spec_parse_rules(input, num_iters, |chunk| {
+        Parser::new(chunk.into_iter()).consume_list_of_rules(true)
+    })
+}
@@ -0,0 +1,123 @@
+use crate::{
+    concat_chunks, iter_size, next_token_start, par_preprocess_mapped, spec_tokenize_chunks,
+};
+use css_lex::*;
+use speculate_lib::SpecStats;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/**
+ * Where the speculative token list first differs from the sequential one.
+ *
+ * Offsets and locations are in terms of the text passed to
+ * `spec_tokenize_verify`, like those of the tokens it returns.
+ */
+#[derive(Debug)]
+pub struct Mismatch {
+    // Index of the first differing token
+    pub index: usize,
+    // Chunk which produced the speculative token at `index`
+    pub chunk: usize,
+    // Byte offset the chunk was split at, before searching for a token start
+    pub chunk_offset: usize,
+    // Where the predictor guessed the chunk's first token starts
+    pub predicted_start: usize,
+    // Where the chunk was actually tokenized from
+    pub used_start: usize,
+    // Where the sequential tokenizer has its first token start at or after
+    // `chunk_offset`
+    pub actual_start: usize,
+    pub expected: Option<Node>,
+    pub got: Option<Node>,
+}
+
+fn node_to_string(node: &Option<Node>) -> String {
+    match node {
+        Some((token, location)) => format!(
+            "{} at {}:{}",
+            token.to_json(),
+            location.line,
+            location.column
+        ),
+        None => "end of input".to_string(),
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "token {} differs: expected {}, got {} (chunk {} split at byte {}: \
+             predicted start {}, used {}, actual {})",
+            self.index,
+            node_to_string(&self.expected),
+            node_to_string(&self.got),
+            self.chunk,
+            self.chunk_offset,
+            self.predicted_start,
+            self.used_start,
+            self.actual_start,
+        )
+    }
+}
+
+impl Error for Mismatch {}
+
+/**
+ * Like `spec_tokenize`, but also run `css_lex::tokenize` on `input` and check
+ * that both produce the same tokens at the same locations.
+ *
+ * Meant for testing the predictor, validation, parallel preprocessing and
+ * location mapping on real stylesheets, as it does all the work of both.
+ */
+pub fn spec_tokenize_verify(
+    input: String,
+    num_iters: usize,
+) -> Result<(SpecStats, Vec<Node>), Box<Mismatch>> {
+    let expected: Vec<Node> = tokenize(&input).collect();
+    let input_len = input.len();
+    let (input, map) = par_preprocess_mapped(input, num_iters);
+    let input = Arc::new(input);
+    let (stats, chunks) = spec_tokenize_chunks(Arc::clone(&input), num_iters);
+    let chunk_starts: Vec<usize> = chunks.iter().map(|c| c.start).collect();
+    let chunk_lens: Vec<usize> = chunks.iter().map(|c| c.nodes.len()).collect();
+    let mut nodes = concat_chunks(chunks);
+    if let Some(map) = &map {
+        map.map_nodes(&mut nodes);
+    }
+
+    let index = match nodes.iter().zip(&expected).position(|(a, b)| a != b) {
+        Some(index) => index,
+        None if nodes.len() == expected.len() => return Ok((stats, nodes)),
+        None => std::cmp::min(nodes.len(), expected.len()),
+    };
+
+    let mut chunk = 0;
+    let mut seen = chunk_lens[0];
+    while seen <= index && chunk + 1 < chunk_lens.len() {
+        chunk += 1;
+        seen += chunk_lens[chunk];
+    }
+    let original = |offset| match &map {
+        Some(map) => map.original_offset(offset),
+        None => offset,
+    };
+    let split_offset = chunk * iter_size(input.len(), num_iters);
+    let chunk_offset = original(split_offset);
+    // Where each token ends, which is where a chunk may start
+    let actual_start = std::iter::once(0)
+        .chain(expected.iter().map(|(_, location)| location.span.end))
+        .find(|&end| end >= chunk_offset)
+        .unwrap_or(input_len);
+    Err(Box::new(Mismatch {
+        index,
+        chunk,
+        chunk_offset,
+        predicted_start: original(next_token_start(Arc::clone(&input), split_offset)),
+        used_start: original(chunk_starts[chunk]),
+        actual_start,
+        expected: expected.get(index).cloned(),
+        got: nodes.get(index).cloned(),
+    }))
+}
@@ -1,7 +1,16 @@
-use css_lex::{json_almost_equals, list_to_json};
+use css_lex::{
+    json_almost_equals, list_to_json, minify, parse_stylesheet, preprocess, tokenize, Node,
+    TextEdit, Token,
+};
 use serde::Serialize;
 use serde_json::Value;
-use spec_css::{next_token_start, spec_tokenize};
+use spec_css::{
+    choose_chunks, next_token_start, par_preprocess, spec_minify, spec_parse_rules,
+    spec_parse_stylesheet, spec_retokenize, spec_tokenize, spec_tokenize_auto, spec_tokenize_bytes,
+    spec_tokenize_many, spec_tokenize_path, spec_tokenize_report, spec_tokenize_rules,
+    spec_tokenize_verify, split_rules, ChunkReason, InputError, MissCause, MIN_CHUNK_SIZE,
+    MIN_SPEC_SIZE,
+};
 use std::sync::Arc;
 
 fn run_json_tests<T: Serialize>(json_data: &str, parse: &dyn Fn(String) -> T) {
@@ -62,3 +71,327 @@ fn test_spec_token_json_3_iter() {
         list_to_json(&spec_tokenize(input, 3).1)
     });
 }
+
+#[test]
+fn test_spec_tokenize_zero_iters() {
+    let css = sample_stylesheet();
+    let expected: Vec<Node> = tokenize(&css).collect();
+    let (stats, nodes) = spec_tokenize(css.clone(), 0);
+    assert!(stats.iters == 1);
+    assert!(nodes == expected);
+    let (_, rules) = spec_tokenize_rules(css, 0);
+    assert!(rules == vec![expected]);
+}
+
+#[test]
+fn test_par_preprocess() {
+    let inputs = [
+        "",
+        "a{b:c}",
+        "a\r\nb\rc\x0Cd\x00e\r\n\r\n",
+        "\r\n\r\r\n\n\r",
+        "é\r\nü\x00ß\r\n",
+    ];
+    for input in inputs {
+        let expected = preprocess(input);
+        for num_chunks in 1..=input.len() + 2 {
+            assert!(par_preprocess(input.to_string(), num_chunks) == expected);
+        }
+    }
+}
+
+// A stylesheet large enough to be split, with comments, strings and urls
+// crossing chunk boundaries.
+fn sample_stylesheet() -> String {
+    let mut css = String::new();
+    for i in 0..300 {
+        css.push_str(&format!(
+            "/* rule {i} */\r\n.cls-{i} > a[href^=\"http://{i}\"] {{\n  margin: {i}px -{i}.5em;\n  \
+             background: url( img/{i}.png ) no-repeat;\n  content: \"é {{ }} ;\";\n}}\n"
+        ));
+    }
+    css
+}
+
+#[test]
+fn test_spec_tokenize_many() {
+    let mut inputs: Vec<String> =
+        match serde_json::from_str(include_str!("../../css_lex/tests/tokens.json")) {
+            Ok(Value::Array(items)) => items
+                .into_iter()
+                .step_by(2)
+                .map(|item| item.as_str().unwrap().to_string())
+                .collect(),
+            _ => panic!("Invalid JSON"),
+        };
+    inputs.push(sample_stylesheet());
+    inputs.push(sample_stylesheet().repeat(3));
+
+    for num_workers in [1, 3, 8] {
+        let results = spec_tokenize_many(inputs.clone(), num_workers);
+        assert!(results.len() == inputs.len());
+        for (input, (stats, nodes)) in inputs.iter().zip(results) {
+            let expected: Vec<_> = tokenize(input).collect();
+            assert!(nodes == expected);
+            if input.len() < MIN_SPEC_SIZE || num_workers == 1 {
+                assert!(stats.iters == 1);
+            } else {
+                assert!(stats.iters > 1);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_choose_chunks() {
+    let small = choose_chunks(MIN_SPEC_SIZE - 1, 8, 10.0);
+    assert!(small.num_iters == 1 && small.reason == ChunkReason::TooSmall);
+
+    let big = choose_chunks(1 << 24, 8, 10.0);
+    assert!(big.num_iters == 8 && big.reason == ChunkReason::Parallelism);
+
+    let short = choose_chunks(MIN_CHUNK_SIZE * 5, 64, 1000.0);
+    assert!(short.num_iters == 5 && short.reason == ChunkReason::ChunkSize);
+
+    let cheap = choose_chunks(1 << 20, 64, 0.1);
+    assert!(cheap.num_iters == 2 && cheap.reason == ChunkReason::SpawnCost);
+}
+
+#[test]
+fn test_spec_tokenize_auto() {
+    for input in ["a{b:c}".to_string(), sample_stylesheet().repeat(4)] {
+        let (stats, nodes) = spec_tokenize_auto(input.clone());
+        let expected: Vec<_> = tokenize(&input).collect();
+        assert!(stats.spec.iters == stats.choice.num_iters);
+        assert!(stats.choice.num_iters <= stats.choice.parallelism.max(1));
+        assert!(nodes == expected);
+    }
+}
+
+#[test]
+fn test_spec_tokenize_verify() {
+    let mut inputs = vec![
+        sample_stylesheet(),
+        "a\n\n  b /* c\n */ d".repeat(50),
+        "a\r\n{ b:\0c }\r\x0C/* \r\n */\r".repeat(60),
+    ];
+    if let Ok(Value::Array(items)) =
+        serde_json::from_str(include_str!("../../css_lex/tests/tokens.json"))
+    {
+        inputs.extend(
+            items
+                .into_iter()
+                .step_by(2)
+                .map(|item| item.as_str().unwrap().to_string()),
+        );
+    }
+    for input in inputs {
+        for num_iters in [1, 2, 3, 7] {
+            if let Err(mismatch) = spec_tokenize_verify(input.clone(), num_iters) {
+                panic!("{}", mismatch);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_spec_tokenize_bytes() {
+    for input in [
+        sample_stylesheet(),
+        sample_stylesheet().replace("\r\n", "\n"),
+    ] {
+        let expected: Vec<_> = tokenize(&input).collect();
+        for num_iters in [1, 3, 8] {
+            let (_, nodes) = spec_tokenize_bytes(input.clone().into_bytes(), num_iters).unwrap();
+            assert!(nodes == expected);
+        }
+        let boxed = input.clone().into_bytes().into_boxed_slice();
+        assert!(spec_tokenize_bytes(boxed, 3).unwrap().1 == expected);
+    }
+    let (_, nodes) = spec_tokenize_bytes(&b"a { b: c }"[..], 2).unwrap();
+    assert!(nodes == tokenize("a { b: c }").collect::<Vec<_>>());
+
+    let mut bytes = "a { b: \"é\" }".repeat(100).into_bytes();
+    bytes[701] = 0xFF;
+    for num_iters in [1, 2, 5, 16] {
+        match spec_tokenize_bytes(bytes.clone(), num_iters) {
+            Err(InputError::Utf8 { offset }) => assert!(offset == 701),
+            _ => panic!("Expected a UTF-8 error"),
+        }
+    }
+}
+
+#[test]
+fn test_spec_tokenize_path() {
+    let path = std::env::temp_dir().join(format!("spec_css_test_{}.css", std::process::id()));
+    let css = sample_stylesheet();
+    std::fs::write(&path, &css).unwrap();
+    let result = spec_tokenize_path(&path, 4);
+    std::fs::remove_file(&path).unwrap();
+    assert!(result.unwrap().1 == tokenize(&css).collect::<Vec<_>>());
+
+    match spec_tokenize_path(&path, 4) {
+        Err(InputError::Io(err)) => assert!(err.kind() == std::io::ErrorKind::NotFound),
+        _ => panic!("Expected an I/O error"),
+    }
+}
+
+#[test]
+fn test_spec_retokenize() {
+    let input = preprocess(&sample_stylesheet());
+    let tokens: Vec<Node> = tokenize(&input).collect();
+    let paste = preprocess(&sample_stylesheet()[..8 * 1024]);
+    for (start, end) in [
+        (0, 0),
+        (1000, 1200),
+        (5000, 5000),
+        (input.len(), input.len()),
+    ] {
+        for replacement in [paste.clone(), format!("/* {paste}"), "\"".to_string()] {
+            let edit = TextEdit {
+                range: start..end,
+                replacement,
+            };
+            let (new_input, new_tokens) = spec_retokenize(&input, &tokens, &edit, 4);
+            let expected: Vec<Node> = tokenize(&new_input).collect();
+            assert!(list_to_json(&new_tokens) == list_to_json(&expected));
+            assert!(new_tokens == expected, "locations differ");
+        }
+    }
+}
+
+#[test]
+fn test_spec_tokenize_rules() {
+    let nested = "@media print {\n.a { b: c }\n}\n/* } */\n.d { e: \"\n}\" }\n".repeat(100);
+    for input in [sample_stylesheet(), nested, "a{b:c}".repeat(500)] {
+        let expected: Vec<Node> = tokenize(&input).collect();
+        for num_iters in [1, 2, 3, 7] {
+            let (stats, rules) = spec_tokenize_rules(input.clone(), num_iters);
+            assert_eq!(rules.len(), num_iters);
+            for rule in &rules[..num_iters - 1] {
+                if let Some((token, _)) = rule.last() {
+                    assert!(*token == Token::RightCurlyBracket);
+                }
+            }
+            let nodes: Vec<Node> = rules.into_iter().flatten().collect();
+            assert!(nodes == expected, "{} iters", num_iters);
+            if input.starts_with("/* rule") {
+                assert!(!stats.mispredictions.contains(&true));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_spec_tokenize_rules_minified() {
+    let minified: String = (0..300)
+        .map(|i| {
+            format!(
+                "/*}}{i}*/.c{i}>a[href^='{{{i}']{{margin:{i}px;content:\"é;\"}}\
+                 @media print{{.d{i}{{e:url(a{i}.png)}}}}"
+            )
+        })
+        .collect();
+    assert!(!minified.contains('\n'));
+    // Braces in strings fool the predictor, but not the validation
+    let braces_in_strings: String = (0..300)
+        .map(|i| format!(".c{i}{{a:\"}}{{\";b:u('}}')}}@media x{{.d{i}{{e:f}}}}"))
+        .collect();
+    for input in [minified, braces_in_strings] {
+        let expected: Vec<Node> = tokenize(&input).collect();
+        for num_iters in [2, 3, 7] {
+            let (stats, rules) = spec_tokenize_rules(input.clone(), num_iters);
+            let validated = stats.mispredictions.iter().filter(|&&miss| !miss).count();
+            assert!(validated > 1, "{} iters", num_iters);
+            assert!(rules.iter().all(|rule| !rule.is_empty()));
+            if input.starts_with("/*") {
+                assert!(!stats.mispredictions.contains(&true), "{} iters", num_iters);
+            }
+            assert!(rules.into_iter().flatten().collect::<Vec<Node>>() == expected);
+        }
+    }
+}
+
+#[test]
+fn test_spec_parse_rules() {
+    let tricky =
+        "a ( {\n} ) { b: c }\n}\n@media print {\n.d { e: [ }\n] }\n}\n@import f;\n".repeat(100);
+    let flat = "a{b:c}".repeat(500);
+    assert_eq!(split_rules(tokenize(&flat).collect()).len(), 500);
+    for input in [sample_stylesheet(), tricky, flat] {
+        let expected = split_rules(tokenize(&input).collect());
+        for num_iters in [1, 2, 3, 7] {
+            let (_, rules) = spec_parse_rules(input.clone(), num_iters, split_rules);
+            assert_eq!(rules, expected, "{} iters", num_iters);
+        }
+    }
+}
+
+#[test]
+fn test_spec_tokenize_report() {
+    let cases = [
+        ("/* 'x */ a ", Some(MissCause::Comment)),
+        ("\"/* aa \" b ", Some(MissCause::String)),
+        ("url(a'bcd) ", Some(MissCause::Url)),
+        ("a\\'bcdefghi c ", Some(MissCause::Escape)),
+        ("a { b: c }\n", None),
+    ];
+    for (unit, cause) in cases {
+        let input = unit.repeat(200);
+        let (report, nodes) = spec_tokenize_report(input.clone(), 11);
+        assert!(nodes == tokenize(&input).collect::<Vec<Node>>());
+        assert_eq!(report.chunks.len(), 11);
+        for chunk in &report.chunks {
+            assert_eq!(
+                chunk.cause.is_some(),
+                report.spec.mispredictions[chunk.chunk]
+            );
+            if chunk.cause.is_none() {
+                assert_eq!(chunk.predicted_start, chunk.actual_start);
+            }
+        }
+        let causes: Vec<MissCause> = report.chunks.iter().filter_map(|c| c.cause).collect();
+        match cause {
+            Some(cause) => assert!(!causes.is_empty() && causes.iter().all(|&c| c == cause)),
+            None => assert!(causes.is_empty()),
+        }
+        assert!(report
+            .to_string()
+            .ends_with(&format!("{} of 11 chunks mispredicted", causes.len())));
+    }
+
+    // Offsets are in terms of the input, before `\r\n` is replaced
+    let lf = "a { b: c }\n".repeat(200);
+    let (lf_report, _) = spec_tokenize_report(lf.clone(), 11);
+    let (crlf_report, _) = spec_tokenize_report(lf.replace('\n', "\r\n"), 11);
+    for (lf_chunk, crlf_chunk) in lf_report.chunks.iter().zip(&crlf_report.chunks) {
+        let original = |offset: usize| offset + lf[..offset].matches('\n').count();
+        assert_eq!(crlf_chunk.actual_start, original(lf_chunk.actual_start));
+        assert_eq!(crlf_chunk.chunk_offset, original(lf_chunk.chunk_offset));
+    }
+}
+
+#[test]
+fn test_spec_minify() {
+    let nested = "@media print {\n.a { b: 0.50px }\n}\n/* } */\n.d { e: \"\n}\" }\n".repeat(100);
+    for input in [sample_stylesheet(), nested] {
+        let expected = minify(&input);
+        for num_iters in [1, 2, 3, 7] {
+            let (_, minified) = spec_minify(input.clone(), num_iters);
+            assert_eq!(minified, expected);
+        }
+    }
+}
+
+#[test]
+fn test_spec_parse_stylesheet() {
+    let tricky =
+        "a ( {\n} ) { b: c }\n}\n@media print {\n.d { e: [ }\n] }\n}\n@import f;\n".repeat(100);
+    for input in [sample_stylesheet(), tricky] {
+        let expected = parse_stylesheet(&input);
+        for num_iters in [1, 2, 3, 7] {
+            let (_, rules) = spec_parse_stylesheet(input.clone(), num_iters);
+            assert_eq!(rules, expected, "{} iters", num_iters);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::str::FromStr;
+use std::{char, sync::Arc};
+
+#[derive(Clone)]
+pub struct NumericValue {
+    pub representation: String,
+    pub value: f64,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub start: usize, // Byte offset of the first byte of the token
+    pub end: usize,   // Byte offset just past the last byte of the token
+}
+
+#[derive(PartialEq, Eq, Clone)]
+pub enum Token {
+    BeginObject,
+    EndObject,
+    BeginArray,
+    EndArray,
+    NameSeparator,
+    ValueSeparator,
+    String(String),
+    BadString,
+    Number(NumericValue),
+    True,
+    False,
+    Null,
+    Invalid(String),
+}
+
+pub type Node = (Token, Span);
+
+pub struct Tokenizer {
+    pub input: Arc<String>,
+    pub length: usize,
+    pub position: usize,
+}
+
+impl PartialEq for NumericValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.representation == other.representation
+            && (self.value - other.value).abs() < f64::EPSILON
+    }
+}
+
+impl Eq for NumericValue {}
+
+impl Tokenizer {
+    pub fn new(input: Arc<String>) -> Tokenizer {
+        Tokenizer {
+            length: input.len(),
+            input,
+            position: 0,
+        }
+    }
+
+    #[inline]
+    fn is_eof(&self) -> bool {
+        self.position >= self.length
+    }
+
+    // Assumes non-EOF
+    #[inline]
+    fn current_byte(&self) -> u8 {
+        self.input.as_bytes()[self.position]
+    }
+
+    #[inline]
+    fn consume_char(&mut self) -> char {
+        let c = self.input[self.position..].chars().next().unwrap();
+
+        self.position += c.len_utf8();
+        c
+    }
+
+    #[inline]
+    fn skip_whitespace(&mut self) {
+        while !self.is_eof() && is_whitespace(self.current_byte()) {
+            self.position += 1;
+        }
+    }
+}
+
+pub fn tokenize(input: &str) -> Tokenizer {
+    Tokenizer::new(Arc::new(input.to_string()))
+}
+
+impl Iterator for Tokenizer {
+    type Item = Node;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        next_token(self)
+    }
+}
+
+// From https://www.rfc-editor.org/rfc/rfc8259#section-2
+#[inline]
+pub fn is_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+// Bytes which may continue a number or a literal name. Used both to lex bare
+// words and by the predictor to tell whether an offset is in the middle of one.
+#[inline]
+pub fn is_word_byte(b: u8) -> bool {
+    matches!(b, b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'+' | b'-' | b'.')
+}
+
+// Whitespace is skipped both before and after each token, so between calls
+// `position` is always either at the start of a token or at EOF.
+fn next_token(tokenizer: &mut Tokenizer) -> Option<Node> {
+    tokenizer.skip_whitespace();
+    if tokenizer.is_eof() {
+        return None;
+    }
+    let start = tokenizer.position;
+
+    let token = match tokenizer.current_byte() {
+        b'{' => {
+            tokenizer.position += 1;
+            Token::BeginObject
+        }
+        b'}' => {
+            tokenizer.position += 1;
+            Token::EndObject
+        }
+        b'[' => {
+            tokenizer.position += 1;
+            Token::BeginArray
+        }
+        b']' => {
+            tokenizer.position += 1;
+            Token::EndArray
+        }
+        b':' => {
+            tokenizer.position += 1;
+            Token::NameSeparator
+        }
+        b',' => {
+            tokenizer.position += 1;
+            Token::ValueSeparator
+        }
+        b'\"' => consume_string(tokenizer),
+        b'-' | b'0'..=b'9' => consume_number(tokenizer),
+        b'a'..=b'z' | b'A'..=b'Z' => consume_literal(tokenizer),
+        _ => Token::Invalid(tokenizer.consume_char().to_string()),
+    };
+    let end = tokenizer.position;
+
+    tokenizer.skip_whitespace();
+    Some((token, Span { start, end }))
+}
+
+// From https://www.rfc-editor.org/rfc/rfc8259#section-7
+//
+// An unterminated string, an unescaped control character or an invalid escape
+// makes the whole string a `BadString`, but lexing always continues to the
+// closing quote so that the token boundaries do not depend on the error.
+fn consume_string(tokenizer: &mut Tokenizer) -> Token {
+    tokenizer.position += 1; // Skip the initial quote
+    let mut string = String::new();
+    let mut valid = true;
+    loop {
+        if tokenizer.is_eof() {
+            return Token::BadString;
+        }
+        match tokenizer.consume_char() {
+            '\"' => break,
+            '\\' => match consume_escape(tokenizer) {
+                Some(c) => string.push(c),
+                None => valid = false,
+            },
+            '\x00'..='\x1F' => valid = false,
+            c => string.push(c),
+        }
+    }
+    if valid {
+        Token::String(string)
+    } else {
+        Token::BadString
+    }
+}
+
+// Assumes that the backslash has already been consumed. Returns None for an
+// invalid escape; lone surrogates decode to U+FFFD.
+fn consume_escape(tokenizer: &mut Tokenizer) -> Option<char> {
+    if tokenizer.is_eof() {
+        return None;
+    }
+    match tokenizer.consume_char() {
+        '\"' => Some('\"'),
+        '\\' => Some('\\'),
+        '/' => Some('/'),
+        'b' => Some('\x08'),
+        'f' => Some('\x0C'),
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        't' => Some('\t'),
+        'u' => {
+            let high = consume_hex4(tokenizer)?;
+            if (0xD800..0xDC00).contains(&high)
+                && tokenizer.input[tokenizer.position..].starts_with("\\u")
+            {
+                let checkpoint = tokenizer.position;
+                tokenizer.position += 2;
+                match consume_hex4(tokenizer) {
+                    Some(low) if (0xDC00..0xE000).contains(&low) => {
+                        let c = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                        return char::from_u32(c);
+                    }
+                    _ => tokenizer.position = checkpoint,
+                }
+            }
+            Some(char::from_u32(high).unwrap_or('\u{FFFD}'))
+        }
+        _ => None,
+    }
+}
+
+fn consume_hex4(tokenizer: &mut Tokenizer) -> Option<u32> {
+    let hex = tokenizer
+        .input
+        .get(tokenizer.position..tokenizer.position + 4)?;
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    tokenizer.position += 4;
+    u32::from_str_radix(hex, 16).ok()
+}
+
+// Consume a run of word bytes starting at the current position.
+fn consume_word(tokenizer: &mut Tokenizer) -> String {
+    let start = tokenizer.position;
+    while !tokenizer.is_eof() && is_word_byte(tokenizer.current_byte()) {
+        tokenizer.position += 1;
+    }
+    tokenizer.input[start..tokenizer.position].to_string()
+}
+
+// From https://www.rfc-editor.org/rfc/rfc8259#section-6
+fn consume_number(tokenizer: &mut Tokenizer) -> Token {
+    let representation = consume_word(tokenizer);
+    if !is_json_number(&representation) {
+        return Token::Invalid(representation);
+    }
+    Token::Number(NumericValue {
+        value: f64::from_str(&representation).unwrap(),
+        representation,
+    })
+}
+
+// Matches -?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?
+fn is_json_number(s: &str) -> bool {
+    fn digits(b: &[u8], mut i: usize) -> usize {
+        while i < b.len() && b[i].is_ascii_digit() {
+            i += 1;
+        }
+        i
+    }
+
+    let b = s.as_bytes();
+    let mut i = 0;
+    if b.first() == Some(&b'-') {
+        i += 1;
+    }
+    match b.get(i) {
+        Some(b'0') => i += 1,
+        Some(b'1'..=b'9') => i = digits(b, i + 1),
+        _ => return false,
+    }
+    if b.get(i) == Some(&b'.') {
+        let j = digits(b, i + 1);
+        if j == i + 1 {
+            return false;
+        }
+        i = j;
+    }
+    if matches!(b.get(i), Some(b'e' | b'E')) {
+        i += 1;
+        if matches!(b.get(i), Some(b'+' | b'-')) {
+            i += 1;
+        }
+        let j = digits(b, i);
+        if j == i {
+            return false;
+        }
+        i = j;
+    }
+    i == b.len()
+}
+
+fn consume_literal(tokenizer: &mut Tokenizer) -> Token {
+    let word = consume_word(tokenizer);
+    match word.as_str() {
+        "true" => Token::True,
+        "false" => Token::False,
+        "null" => Token::Null,
+        _ => Token::Invalid(word),
+    }
+}
@@ -0,0 +1,120 @@
+pub use lexer::*;
+pub use to_json::*;
+
+pub mod lexer;
+pub mod to_json;
+
+use speculate_lib::*;
+use std::sync::Arc;
+
+// Whitespace produces no tokens and every other token is usually a one-byte
+// bracket or separator, so compact JSON averages about four bytes per token.
+// Indented JSON has more, which only leaves some of each chunk's buffer unused.
+static BYTES_PER_TOKEN: usize = 4;
+
+/**
+ * Guess whether the byte at `start` lies inside a string.
+ *
+ * Unlike CSS, there is no way to back up a few characters and re-lex, since a
+ * string can be arbitrarily long. Instead, look at the first unescaped quote
+ * at or after `start` and guess its parity from what follows it: in valid JSON
+ * a closing quote is always followed by `:`, `,`, `}`, `]` or the end of the
+ * input, while an opening quote is followed by the string's contents. This
+ * only guesses wrong when `start` is outside a string and the next string
+ * begins with one of those characters, e.g. `", "`.
+ *
+ * Returns the position of that quote if it is guessed to be a closing one.
+ */
+fn closing_quote_after(input: &[u8], start: usize) -> Option<usize> {
+    let mut position = start;
+    while position < input.len() {
+        if input[position] == b'\"' {
+            let backslashes = input[..position]
+                .iter()
+                .rev()
+                .take_while(|&&b| b == b'\\')
+                .count();
+            if backslashes % 2 == 0 {
+                let follower = input[position + 1..].iter().find(|&&b| !is_whitespace(b));
+                return match follower {
+                    None | Some(b':' | b',' | b'}' | b']') => Some(position),
+                    _ => None,
+                };
+            }
+        }
+        position += 1;
+    }
+    None
+}
+
+/**
+ * Find the start of the next token at or after `start`.
+ *
+ * If `start` is guessed to be inside a string the next token begins after its
+ * closing quote. Otherwise skip the rest of any number or literal that
+ * `start` falls in the middle of. Either way, trailing whitespace is skipped,
+ * matching where the tokenizer leaves its position after each token.
+ */
+pub fn next_token_start(input: Arc<String>, start: usize) -> usize {
+    let bytes = input.as_bytes();
+    let mut position = start.min(bytes.len());
+    while !input.is_char_boundary(position) {
+        position += 1;
+    }
+
+    match closing_quote_after(bytes, position) {
+        // Nothing comes before the first byte, so it cannot be inside a string
+        Some(quote) if position > 0 => position = quote + 1,
+        _ => {
+            if position > 0 && position < bytes.len() {
+                while position < bytes.len()
+                    && is_word_byte(bytes[position - 1])
+                    && is_word_byte(bytes[position])
+                {
+                    position += 1;
+                }
+            }
+        }
+    }
+
+    while position < bytes.len() && is_whitespace(bytes[position]) {
+        position += 1;
+    }
+    position
+}
+
+/**
+ * Tokenize `input` in `num_iters` speculative chunks, or in one if
+ * `num_iters` is zero.
+ */
+pub fn spec_tokenize(input: String, num_iters: usize) -> (SpecStats, Vec<Node>) {
+    let num_iters = num_iters.max(1);
+    let json_len = input.len();
+    let str_arc = Arc::new(input);
+    let predictor_arc = Arc::clone(&str_arc);
+
+    let iter_size = json_len.div_ceil(num_iters);
+
+    // LOOP_BODY
+
+    let loop_body = move |idx: usize, token_start: &usize| {
+        let upper = std::cmp::min((idx + 1) * iter_size, json_len);
+        let mut tokenizer = Tokenizer::new(Arc::clone(&str_arc));
+        tokenizer.position = *token_start;
+        let mut results: Vec<Node> =
+            Vec::with_capacity(upper.saturating_sub(*token_start) / BYTES_PER_TOKEN);
+        while tokenizer.position < upper {
+            match tokenizer.next() {
+                Some(node) => results.push(node),
+                None => break,
+            }
+        }
+        (tokenizer.position, results)
+    };
+
+    // PREDICTOR
+
+    let predictor = move |idx| next_token_start(Arc::clone(&predictor_arc), idx * iter_size);
+    let (res, chunks) = specfold_results(num_iters, loop_body, predictor);
+    (res, concat_results(chunks))
+}
@@ -0,0 +1,34 @@
+use crate::lexer::*;
+use serde_json::{json, Value};
+
+impl Token {
+    pub fn to_json(&self) -> Value {
+        match self {
+            Token::BeginObject => json!("{"),
+            Token::EndObject => json!("}"),
+            Token::BeginArray => json!("["),
+            Token::EndArray => json!("]"),
+            Token::NameSeparator => json!(":"),
+            Token::ValueSeparator => json!(","),
+            Token::String(value) => json!(["string", value]),
+            Token::BadString => json!(["error", "bad-string"]),
+            Token::Number(value) => json!(["number", value.representation, value.value]),
+            Token::True => json!("true"),
+            Token::False => json!("false"),
+            Token::Null => json!("null"),
+            Token::Invalid(value) => json!(["error", "invalid", value]),
+        }
+    }
+}
+
+impl Span {
+    pub fn to_json(&self) -> Value {
+        json!([self.start, self.end])
+    }
+}
+
+pub fn list_to_json(list: &[Node]) -> Vec<Value> {
+    list.iter()
+        .map(|(token, span)| json!([token.to_json(), span.to_json()]))
+        .collect()
+}
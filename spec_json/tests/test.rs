@@ -0,0 +1,114 @@
+use serde_json::Value;
+use spec_json::*;
+use std::sync::Arc;
+
+fn run_json_tests(json_data: &str, parse: &dyn Fn(String) -> Vec<Value>) {
+    let items = match serde_json::from_str::<Value>(json_data) {
+        Ok(Value::Array(items)) => items,
+        _ => panic!("Invalid JSON"),
+    };
+    assert!(items.len() % 2 == 0, "Items list should be even");
+    let mut input: Option<String> = None;
+    for item in items {
+        match (&input, item) {
+            (None, Value::String(string)) => input = Some(string),
+            (Some(_), expected) => {
+                let json = input.take().expect("Input was None");
+                let result = Value::Array(parse(json.clone()));
+                if result != expected {
+                    panic!("input: {:?}\ngot: {}\nexpected: {}", json, result, expected);
+                }
+            }
+            _ => panic!("Unexpected JSON"),
+        };
+    }
+}
+
+// A manifest-like document with long strings full of structural characters.
+fn sample_document() -> String {
+    let mut json = String::from("{\"targets\": [\n");
+    for i in 0..200 {
+        json.push_str(&format!(
+            "  {{\"name\": \"target-{i}\", \"deps\": [\"a, b\", \"{{c}}\", \"d\\\"e\"], \
+             \"weight\": {}.{}e-2, \"enabled\": {}, \"notes\": null, \"path\": \"src/é/{i}\"}},\n",
+            i * 7,
+            i % 10,
+            i % 2 == 0
+        ));
+    }
+    json.push_str("  {}\n]}\n");
+    json
+}
+
+#[test]
+fn tokenize_simple() {
+    let mut t = tokenize("[1]");
+    assert!(t.next() == Some((Token::BeginArray, Span { start: 0, end: 1 })));
+}
+
+#[test]
+fn test_next_token_start() {
+    let json = Arc::new(String::from("{\"key\": [12, \"a, b\"], \"x\": true}"));
+
+    assert!(next_token_start(json.clone(), 0) == 0);
+    assert!(next_token_start(json.clone(), 3) == 6);
+    assert!(next_token_start(json.clone(), 7) == 8);
+    assert!(next_token_start(json.clone(), 10) == 11);
+    assert!(next_token_start(json.clone(), 16) == 19);
+    assert!(next_token_start(json.clone(), 29) == 31);
+    assert!(next_token_start(json.clone(), 33) == 32);
+}
+
+#[test]
+fn test_tokenize_json() {
+    run_json_tests(include_str!("tokens.json"), &|input| {
+        list_to_json(&tokenize(&input).collect::<Vec<_>>())
+    });
+}
+
+#[test]
+fn test_spec_token_json_1_iter() {
+    run_json_tests(include_str!("tokens.json"), &|input| {
+        list_to_json(&spec_tokenize(input, 1).1)
+    });
+}
+
+#[test]
+fn test_spec_token_json_2_iter() {
+    run_json_tests(include_str!("tokens.json"), &|input| {
+        list_to_json(&spec_tokenize(input, 2).1)
+    });
+}
+
+#[test]
+fn test_spec_token_json_3_iter() {
+    run_json_tests(include_str!("tokens.json"), &|input| {
+        list_to_json(&spec_tokenize(input, 3).1)
+    });
+}
+
+#[test]
+fn test_spec_token_json_7_iter() {
+    run_json_tests(include_str!("tokens.json"), &|input| {
+        list_to_json(&spec_tokenize(input, 7).1)
+    });
+}
+
+#[test]
+fn test_spec_tokenize_matches_sequential() {
+    let json = sample_document();
+    let expected: Vec<Node> = tokenize(&json).collect();
+    for num_iters in 1..=16 {
+        let (stats, nodes) = spec_tokenize(json.clone(), num_iters);
+        assert!(stats.iters == num_iters);
+        assert!(nodes == expected, "mismatch with {} iterations", num_iters);
+    }
+}
+
+#[test]
+fn test_spec_tokenize_zero_iters() {
+    let json = sample_document();
+    let (stats, nodes) = spec_tokenize(json.clone(), 0);
+    assert!(stats.iters == 1);
+    assert!(nodes == tokenize(&json).collect::<Vec<Node>>());
+}
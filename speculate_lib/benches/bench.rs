@@ -23,7 +23,7 @@ fn bench_2048(c: &mut Criterion) {
             spec(
                 move || {
                     let local_arc = rx.recv().unwrap();
-                    local_arc.iter().fold(0, |old, &new| old + new)
+                    local_arc.iter().sum::<usize>()
                 },
                 || 2096128,
                 move |x| {
@@ -44,7 +44,7 @@ fn bench_2048(c: &mut Criterion) {
             spec(
                 move || {
                     let local_arc = rx.recv().unwrap();
-                    local_arc.iter().fold(0, |old, &new| old + new)
+                    local_arc.iter().sum::<usize>()
                 },
                 || 0, // Incorrect result of `fold`
                 move |x| {
@@ -78,7 +78,7 @@ fn bench_65536(c: &mut Criterion) {
             spec(
                 move || {
                     let local_arc = rx.recv().unwrap();
-                    local_arc.iter().fold(0, |old, &new| old + new)
+                    local_arc.iter().sum::<usize>()
                 },
                 || 2147450880,
                 move |x| {
@@ -100,7 +100,7 @@ fn bench_65536(c: &mut Criterion) {
             spec(
                 move || {
                     let local_arc = rx.recv().unwrap();
-                    local_arc.iter().fold(0, |old, &new| old + new)
+                    local_arc.iter().sum::<usize>()
                 },
                 || 0, // Incorrect result of `fold`
                 move |x| {
@@ -39,6 +39,27 @@ pub fn specfold<A: Eq + Clone + Send + 'static>(
     loop_body: impl Fn(usize, &A) -> A + Send + Clone + 'static,
     predictor: impl Fn(usize) -> A + Send + Clone + 'static,
 ) -> SpecStats {
+    let (stats, _) = specfold_results(
+        iters,
+        move |idx: usize, val: &A| (loop_body(idx, val), ()),
+        predictor,
+    );
+    stats
+}
+
+/**
+ * Like `specfold`, but `loop_body` also produces a per-iteration result.
+ *
+ * The results are returned in iteration order. When a prediction was wrong,
+ * the result of the re-executed iteration replaces the speculative one, so the
+ * caller never sees output computed from a bad guess.
+ */
+pub fn specfold_results<A: Eq + Clone + Send + 'static, R: Send + 'static>(
+    iters: usize,
+    loop_body: impl Fn(usize, &A) -> (A, R) + Send + Clone + 'static,
+    predictor: impl Fn(usize) -> A + Send + Clone + 'static,
+) -> (SpecStats, Vec<R>) {
+    let mut handles = Vec::with_capacity(iters);
     let mut results = Vec::with_capacity(iters);
     let mut stats = SpecStats {
         iters,
@@ -51,22 +72,36 @@ pub fn specfold<A: Eq + Clone + Send + 'static>(
 
         let thread = thread::spawn(move || {
             let prediction = predictor_clone(i);
-            let res = loop_body_clone(i, &prediction);
-            (prediction, res)
+            let (next, res) = loop_body_clone(i, &prediction);
+            (prediction, next, res)
         });
-        results.push(thread);
+        handles.push(thread);
     }
 
     let mut previous: Option<A> = None;
-    for (i, handle) in results.into_iter().enumerate() {
-        let (prediction, _) = handle.join().unwrap();
-        if let Some(prev) = &previous {
-            if *prev != prediction {
+    for (i, handle) in handles.into_iter().enumerate() {
+        let (prediction, next, res) = handle.join().unwrap();
+        let (next, res) = match previous {
+            Some(prev) if prev != prediction => {
                 stats.mispredictions[i] = true;
-                let res = loop_body(i, prev);
-                previous = Some(res);
+                loop_body(i, &prev)
             }
-        }
+            _ => (next, res),
+        };
+        previous = Some(next);
+        results.push(res);
     }
-    stats
+    (stats, results)
+}
+
+/**
+ * Join per-iteration results, such as the token lists of `specfold_results`,
+ * into one exactly sized vector, moving the items rather than cloning them.
+ */
+pub fn concat_results<R>(results: Vec<Vec<R>>) -> Vec<R> {
+    let mut joined = Vec::with_capacity(results.iter().map(Vec::len).sum());
+    for mut result in results {
+        joined.append(&mut result);
+    }
+    joined
 }
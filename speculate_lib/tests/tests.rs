@@ -50,7 +50,7 @@ fn test_specfold_correct_prediction() {
         tx.send(Some((idx, res))).unwrap();
         res
     };
-    let loop_results = vec![0, 0, 1, 3, 6];
+    let loop_results = [0, 0, 1, 3, 6];
     let predictor = move |idx: usize| loop_results[idx];
 
     spawn_result_collector(rx, res_tx, 5);
@@ -99,3 +99,50 @@ fn test_specfold_no_tasks() {
     let expected_results = vec![];
     assert!(res_rx.recv().unwrap() == expected_results);
 }
+
+#[test]
+fn test_specfold_detects_mispredictions() {
+    let loop_body = |idx: usize, val: &isize| -> isize { idx as isize + val };
+    // Only the first two iterations start from the value the previous
+    // iteration produces
+    let predictor = |_| 0;
+
+    let stats = specfold(4, loop_body, predictor);
+    assert_eq!(stats.mispredictions, vec![false, false, true, true]);
+}
+
+#[test]
+fn test_specfold_results_correct_prediction() {
+    let loop_body = |idx: usize, val: &isize| {
+        let res = idx as isize + val;
+        (res, res * 10)
+    };
+    let loop_results = [0, 0, 1, 3, 6];
+    let predictor = move |idx: usize| loop_results[idx];
+
+    let (stats, results) = specfold_results(5, loop_body, predictor);
+    assert!(stats.mispredictions == vec![false; 5]);
+    assert!(results == vec![0, 10, 30, 60, 100]);
+}
+
+#[test]
+fn test_specfold_results_incorrect_prediction() {
+    let loop_body = |idx: usize, val: &isize| {
+        let res = idx as isize + val;
+        (res, res * 10)
+    };
+    let predictor = |_| 0;
+
+    let (stats, results) = specfold_results(4, loop_body, predictor);
+    assert!(stats.mispredictions == vec![false, false, true, true]);
+    assert!(results == vec![0, 10, 30, 60]);
+}
+
+#[test]
+fn test_concat_results() {
+    assert_eq!(
+        concat_results(vec![vec![1, 2], vec![], vec![3]]),
+        vec![1, 2, 3]
+    );
+    assert_eq!(concat_results::<u8>(vec![]), vec![]);
+}